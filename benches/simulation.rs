@@ -0,0 +1,44 @@
+//! Run with `cargo bench --features bench`. Scene benchmarks report their throughput in steps per
+//! second.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use rogue_mage::bench::{scene_names, CastBench, PropertyBench, SceneBench};
+
+/// How many steps each sample of a scene runs from its start, covering a second of the game
+const STEPS_PER_SAMPLE: usize = 60;
+
+fn step(c: &mut Criterion) {
+    let mut group = c.benchmark_group("step");
+    group.throughput(Throughput::Elements(STEPS_PER_SAMPLE as u64));
+    group.sample_size(20);
+    for name in scene_names() {
+        group.bench_function(name, |b| {
+            b.iter_batched_ref(
+                || SceneBench::new(name),
+                |scene| scene.run(STEPS_PER_SAMPLE),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn cast(c: &mut Criterion) {
+    let mut group = c.benchmark_group("cast");
+    for spell in CastBench::spells() {
+        let bench = CastBench::new(spell);
+        group.bench_function(spell, |b| b.iter(|| bench.cast()));
+    }
+    group.finish();
+}
+
+fn properties(c: &mut Criterion) {
+    let mut group = c.benchmark_group("properties");
+    let mut bench = PropertyBench::default();
+    group.bench_function("set", |b| b.iter(|| bench.set()));
+    group.bench_function("swap_properties", |b| b.iter(|| bench.swap_properties()));
+    group.finish();
+}
+
+criterion_group!(benches, step, cast, properties);
+criterion_main!(benches);
@@ -0,0 +1,118 @@
+//! The parts of the simulation measured by the benchmarks in `benches/`, which can only reach
+//! what's public
+
+use crate::blocks::*;
+use crate::cells::{set_block_range, GRID_SIZE};
+use crate::chemistry::DynamicProperty::*;
+use crate::chemistry::*;
+use crate::harness::{Scene, SCENARIOS};
+use crate::rules::{UpdateRules, TIMESTEP};
+use crate::spells::{Spell, SpellTarget};
+
+/// The names of the standard scenes
+pub fn scene_names() -> impl Iterator<Item = &'static str> {
+    SCENARIOS.iter().map(|scenario| scenario.name)
+}
+
+/// A standard scene, stepped with the rules its scenario runs with
+pub struct SceneBench {
+    scene: Scene,
+    update_rules: UpdateRules,
+}
+
+impl SceneBench {
+    pub fn new(name: &str) -> SceneBench {
+        let scenario = SCENARIOS
+            .iter()
+            .find(|scenario| scenario.name == name)
+            .unwrap_or_else(|| panic!("no scene named {}", name));
+        SceneBench {
+            scene: scenario.initial(),
+            update_rules: (scenario.rules)(),
+        }
+    }
+
+    pub fn run(&mut self, steps: usize) {
+        self.scene.run(&self.update_rules, steps, TIMESTEP);
+    }
+}
+
+/// The spells cast by `CastBench`, reaching more and more targets
+const FAN_OUT_SPELLS: [&str; 6] = [
+    "adjacent => (add Burning)",
+    "adjacent adjacent => (add Burning)",
+    "adjacent adjacent adjacent => (add Burning)",
+    "(area 8) Water => (add Burning)",
+    "(area 32) Water => (add Burning)",
+    "(sight 32) => (add Burning)",
+];
+
+/// A spell cast from the middle of a world of water, air and stone pillars
+pub struct CastBench {
+    info: WorldInfo,
+    spell: Spell,
+}
+
+impl CastBench {
+    /// The spells that can be cast, written as spells are written in traces
+    pub fn spells() -> impl Iterator<Item = &'static str> {
+        FAN_OUT_SPELLS.into_iter()
+    }
+
+    pub fn new(spell: &str) -> CastBench {
+        let size = GRID_SIZE as i32;
+        let mut info = WorldInfo::default();
+        set_block_range(&mut info, 0..size, 0..size / 2, *WATER);
+        for x in (0..size).step_by(8) {
+            set_block_range(&mut info, x..x + 1, 0..size, *STONE);
+        }
+        CastBench {
+            info,
+            spell: spell.parse().unwrap(),
+        }
+    }
+
+    /// Cast the spell, returning how many targets it reached
+    pub fn cast(&self) -> usize {
+        let center = GRID_SIZE as i32 / 2 + 3;
+        let mut targets = 0;
+        self.spell.cast(
+            &self.info,
+            SpellTarget::new(Target::Block(center, center)),
+            &mut |_| targets += 1,
+        );
+        targets
+    }
+}
+
+/// Setting and swapping the dynamic properties of blocks
+#[derive(Default)]
+pub struct PropertyBench {
+    info: WorldInfo,
+    /// The block to change next, counting row by row
+    next: i32,
+}
+
+impl PropertyBench {
+    fn next_block(&mut self) -> (i32, i32) {
+        let size = GRID_SIZE as i32;
+        self.next = (self.next + 1) % (size * (size - 1));
+        (self.next % size, self.next / size)
+    }
+
+    /// Set a property on the next block, and take it away again
+    pub fn set(&mut self) {
+        let (x, y) = self.next_block();
+        self.info.set(Target::Block(x, y), Burning, 1.0);
+        self.info.set(Target::Block(x, y), Burning, 0.0);
+    }
+
+    /// Swap the properties of the next block and the block above it, only one of which has any
+    pub fn swap_properties(&mut self) {
+        let (x, y) = self.next_block();
+        self.info.set(Target::Block(x, y), Burning, 1.0);
+        self.info
+            .swap_properties(Target::Block(x, y), Target::Block(x, y + 1));
+        self.info.set(Target::Block(x, y + 1), Burning, 0.0);
+    }
+}
@@ -46,10 +46,9 @@ impl Block {
         }
     }
 
-    pub(crate) fn color(&self) -> Color {
-        let x = self.color_seed as f32 / 255.0;
-        let data = self.data();
-        data.color1 * x + data.color2 * (1.0 - x)
+    /// How far between the two colors of its material this block is drawn
+    pub(crate) fn color_seed(&self) -> u8 {
+        self.color_seed
     }
 
     pub(crate) fn data(&self) -> &'static BlockData {
@@ -65,13 +64,19 @@ impl Block {
     }
 
     pub(crate) fn iter_properties<'a>(&'a self) -> impl Iterator<Item = Property> + 'a {
-        [Property::Material(self.id)].into_iter().chain(
-            if self.data().physics == BlockPhysics::Liquid {
+        let data = self.data();
+        [Property::Material(self.id)]
+            .into_iter()
+            .chain(if data.physics == BlockPhysics::Liquid {
                 Some(Static(Liquid))
             } else {
                 None
-            },
-        )
+            })
+            .chain(if data.flammability > 0.0 {
+                Some(Static(Flammable))
+            } else {
+                None
+            })
     }
 }
 
@@ -85,6 +90,20 @@ pub(crate) enum BlockPhysics {
     Liquid,
 }
 
+/// An animation applied to the colors of a block by the block shader - the order must match the
+/// animation constants in blocks.wgsl
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum BlockAnimation {
+    /// Keeps the color given by the color seed
+    None,
+    /// Jumps randomly between the two colors, like flames
+    Flicker,
+    /// Waves of light rolling across the surface, like water
+    Shimmer,
+    /// Swirls of color drifting upwards, like smoke
+    Drift,
+}
+
 #[derive(Debug)]
 pub(crate) struct BlockData {
     /// Internal block name
@@ -99,6 +118,22 @@ pub(crate) struct BlockData {
     pub(crate) physics: BlockPhysics,
     /// Stability of this powder - only makes sense for powders
     pub(crate) powder_stability: f32,
+    /// How strongly spells and reactions reach into this block from its neighbors
+    pub(crate) conductivity: f32,
+    /// How the colors of this block move over time
+    pub(crate) animation: BlockAnimation,
+    /// How brightly this block glows onto its surroundings, from 0 to 1
+    pub(crate) glow: f32,
+    /// How much of the light passing through this block it absorbs, from 0 to 1
+    pub(crate) opacity: f32,
+    /// The column and row of the tile in the tileset drawn for this block in autotiled mode, if any
+    pub(crate) tile: Option<(u32, u32)>,
+    /// How well electricity flows through this block, from 0 to 1
+    pub(crate) electric_conductance: f32,
+    /// The electric potential this block holds itself at, if it's a source of electricity
+    pub(crate) voltage: Option<f32>,
+    /// How easily fire spreads to this block, from 0 to 1
+    pub(crate) flammability: f32,
 }
 
 fn get_id(name: &str) -> u16 {
@@ -114,6 +149,14 @@ lazy_static! {
             density: 0.0,
             physics: BlockPhysics::None,
             powder_stability: 0.0,
+            conductivity: 1.0,
+            animation: BlockAnimation::None,
+            glow: 0.0,
+            opacity: 0.0,
+            tile: None,
+            electric_conductance: 0.0,
+            voltage: None,
+            flammability: 0.0,
         },
         BlockData {
             name: "Stone",
@@ -122,6 +165,14 @@ lazy_static! {
             density: 3.3,
             physics: BlockPhysics::Solid,
             powder_stability: 0.0,
+            conductivity: 0.2,
+            animation: BlockAnimation::None,
+            glow: 0.0,
+            opacity: 1.0,
+            tile: Some((0, 1)),
+            electric_conductance: 0.0,
+            voltage: None,
+            flammability: 0.0,
         },
         BlockData {
             name: "Water",
@@ -130,6 +181,14 @@ lazy_static! {
             density: 2.9,
             physics: BlockPhysics::Liquid,
             powder_stability: 0.0,
+            conductivity: 1.0,
+            animation: BlockAnimation::Shimmer,
+            glow: 0.0,
+            opacity: 0.2,
+            tile: None,
+            electric_conductance: 0.5,
+            voltage: None,
+            flammability: 0.0,
         },
         BlockData {
             name: "Sand",
@@ -138,6 +197,14 @@ lazy_static! {
             density: 3.3,
             physics: BlockPhysics::Liquid,
             powder_stability: 0.3,
+            conductivity: 0.5,
+            animation: BlockAnimation::None,
+            glow: 0.0,
+            opacity: 1.0,
+            tile: None,
+            electric_conductance: 0.0,
+            voltage: None,
+            flammability: 0.0,
         },
         BlockData {
             name: "Wood",
@@ -146,6 +213,14 @@ lazy_static! {
             density: 2.7,
             physics: BlockPhysics::Solid,
             powder_stability: 0.0,
+            conductivity: 0.5,
+            animation: BlockAnimation::None,
+            glow: 0.0,
+            opacity: 1.0,
+            tile: Some((4, 0)),
+            electric_conductance: 0.0,
+            voltage: None,
+            flammability: 0.5,
         },
         BlockData {
             name: "Coal",
@@ -154,6 +229,14 @@ lazy_static! {
             density: 3.0,
             physics: BlockPhysics::Liquid,
             powder_stability: 0.7,
+            conductivity: 1.0,
+            animation: BlockAnimation::None,
+            glow: 0.0,
+            opacity: 1.0,
+            tile: None,
+            electric_conductance: 0.0,
+            voltage: None,
+            flammability: 1.0,
         },
         BlockData {
             name: "Fire",
@@ -162,6 +245,14 @@ lazy_static! {
             density: 0.0,
             physics: BlockPhysics::None,
             powder_stability: 0.0,
+            conductivity: 1.0,
+            animation: BlockAnimation::Flicker,
+            glow: 1.0,
+            opacity: 0.0,
+            tile: None,
+            electric_conductance: 0.0,
+            voltage: None,
+            flammability: 0.0,
         },
         BlockData {
             name: "Smoke",
@@ -170,6 +261,14 @@ lazy_static! {
             density: -0.6,
             physics: BlockPhysics::Liquid,
             powder_stability: 0.0,
+            conductivity: 1.0,
+            animation: BlockAnimation::Drift,
+            glow: 0.0,
+            opacity: 0.4,
+            tile: None,
+            electric_conductance: 0.0,
+            voltage: None,
+            flammability: 0.0,
         },
         BlockData {
             name: "Steam",
@@ -178,6 +277,110 @@ lazy_static! {
             density: -0.3,
             physics: BlockPhysics::Liquid,
             powder_stability: 0.0,
+            conductivity: 1.0,
+            animation: BlockAnimation::Drift,
+            glow: 0.0,
+            opacity: 0.2,
+            tile: None,
+            electric_conductance: 0.0,
+            voltage: None,
+            flammability: 0.0,
+        },
+        BlockData {
+            name: "Metal",
+            color1: Color::rgb(0.7, 0.7, 0.75),
+            color2: Color::rgb(0.55, 0.55, 0.6),
+            density: 7.8,
+            physics: BlockPhysics::Solid,
+            powder_stability: 0.0,
+            conductivity: 1.0,
+            animation: BlockAnimation::None,
+            glow: 0.0,
+            opacity: 1.0,
+            tile: Some((6, 1)),
+            electric_conductance: 1.0,
+            voltage: None,
+            flammability: 0.0,
+        },
+        BlockData {
+            name: "Battery",
+            color1: Color::rgb(0.9, 0.8, 0.1),
+            color2: Color::rgb(0.8, 0.7, 0.1),
+            density: 7.8,
+            physics: BlockPhysics::Solid,
+            powder_stability: 0.0,
+            conductivity: 1.0,
+            animation: BlockAnimation::None,
+            glow: 0.0,
+            opacity: 1.0,
+            tile: None,
+            electric_conductance: 1.0,
+            voltage: Some(1.0),
+            flammability: 0.0,
+        },
+        BlockData {
+            name: "Dirt",
+            color1: Color::rgb(0.45, 0.3, 0.15),
+            color2: Color::rgb(0.35, 0.22, 0.1),
+            density: 3.0,
+            physics: BlockPhysics::Liquid,
+            powder_stability: 0.8,
+            conductivity: 0.5,
+            animation: BlockAnimation::None,
+            glow: 0.0,
+            opacity: 1.0,
+            tile: Some((2, 0)),
+            electric_conductance: 0.0,
+            voltage: None,
+            flammability: 0.0,
+        },
+        BlockData {
+            name: "Grass",
+            color1: Color::rgb(0.35, 0.75, 0.2),
+            color2: Color::rgb(0.2, 0.5, 0.1),
+            density: 3.0,
+            physics: BlockPhysics::None,
+            powder_stability: 0.0,
+            conductivity: 0.5,
+            animation: BlockAnimation::None,
+            glow: 0.0,
+            opacity: 1.0,
+            tile: Some((0, 0)),
+            electric_conductance: 0.0,
+            voltage: None,
+            flammability: 1.0,
+        },
+        BlockData {
+            name: "Vine",
+            color1: Color::rgb(0.2, 0.55, 0.15),
+            color2: Color::rgb(0.1, 0.4, 0.1),
+            density: 1.0,
+            physics: BlockPhysics::None,
+            powder_stability: 0.0,
+            conductivity: 0.5,
+            animation: BlockAnimation::None,
+            glow: 0.0,
+            opacity: 0.5,
+            tile: None,
+            electric_conductance: 0.0,
+            voltage: None,
+            flammability: 1.0,
+        },
+        BlockData {
+            name: "Seed",
+            color1: Color::rgb(0.65, 0.55, 0.25),
+            color2: Color::rgb(0.5, 0.4, 0.15),
+            density: 1.5,
+            physics: BlockPhysics::Liquid,
+            powder_stability: 0.0,
+            conductivity: 0.5,
+            animation: BlockAnimation::None,
+            glow: 0.0,
+            opacity: 1.0,
+            tile: None,
+            electric_conductance: 0.0,
+            voltage: None,
+            flammability: 1.0,
         },
     ];
     pub(crate) static ref AIR: u16 = get_id("Air");
@@ -189,4 +392,10 @@ lazy_static! {
     pub(crate) static ref FIRE: u16 = get_id("Fire");
     pub(crate) static ref SMOKE: u16 = get_id("Smoke");
     pub(crate) static ref STEAM: u16 = get_id("Steam");
+    pub(crate) static ref METAL: u16 = get_id("Metal");
+    pub(crate) static ref BATTERY: u16 = get_id("Battery");
+    pub(crate) static ref DIRT: u16 = get_id("Dirt");
+    pub(crate) static ref GRASS: u16 = get_id("Grass");
+    pub(crate) static ref VINE: u16 = get_id("Vine");
+    pub(crate) static ref SEED: u16 = get_id("Seed");
 }
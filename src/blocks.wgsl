@@ -0,0 +1,210 @@
+#import bevy_sprite::mesh2d_view_bind_group
+#import bevy_sprite::mesh2d_struct
+
+struct BlockGlobals {
+    time: f32;
+    burning_material: u32;
+    autotile: u32;
+};
+
+[[group(0), binding(0)]]
+var<uniform> view: View;
+
+[[group(1), binding(0)]]
+var<uniform> globals: BlockGlobals;
+// The material id, color seed, burning and light of every block in the chunk and its border
+[[group(1), binding(1)]]
+var blocks: texture_2d<f32>;
+// Two colors, the animation and glow, then the tile, of every block type
+[[group(1), binding(2)]]
+var materials: texture_2d<f32>;
+[[group(1), binding(3)]]
+var tileset: texture_2d<f32>;
+// The pieces that shape tiles to their neighbors, see `autotile`
+[[group(1), binding(4)]]
+var pieces: texture_2d<f32>;
+
+[[group(2), binding(0)]]
+var<uniform> mesh: Mesh2d;
+
+struct FragmentInput {
+    [[builtin(front_facing)]] is_front: bool;
+    [[location(0)]] world_position: vec4<f32>;
+    [[location(1)]] world_normal: vec3<f32>;
+    [[location(2)]] uv: vec2<f32>;
+#ifdef VERTEX_TANGENTS
+    [[location(3)]] world_tangent: vec4<f32>;
+#endif
+};
+
+// Must match BlockAnimation in blocks.rs
+let ANIMATION_FLICKER: i32 = 1;
+let ANIMATION_SHIMMER: i32 = 2;
+let ANIMATION_DRIFT: i32 = 3;
+
+// How much of its color a block with no light reaching it keeps
+let MIN_LIGHT: f32 = 0.1;
+// How dark the shadows over the background are where no light reaches
+let SHADOW_OPACITY: f32 = 0.85;
+
+// The size of a tile in the tileset, in texels
+let TILE_TEXELS: i32 = 16;
+// The number of blocks a tile is stretched over
+let TILE_BLOCKS: f32 = 4.0;
+
+// The size of an autotile piece, each covering a single block, in texels
+let PIECE_TEXELS: i32 = 8;
+// The autotile pieces, in the order they're laid out in autotile.png. Each piece shapes a block
+// with every side exposed the way it's named after, so that any quarter of it can be used.
+let PIECE_FILL: i32 = 0;
+let PIECE_OUTER_CORNERS: i32 = 1;
+let PIECE_HORIZONTAL_EDGES: i32 = 2;
+let PIECE_VERTICAL_EDGES: i32 = 3;
+let PIECE_INNER_CORNERS: i32 = 4;
+
+// How many blocks past the edges of the chunk the chunk texture holds, must match TEXTURE_BORDER
+// in cells.rs
+let TEXTURE_BORDER: i32 = 3;
+
+// How far glowing blocks light up their surroundings, in blocks - no further than the border
+let GLOW_RADIUS: i32 = 3;
+// How opaque the glow of a fully glowing block is
+let GLOW_STRENGTH: f32 = 0.6;
+
+fn hash(p: vec2<f32>) -> f32 {
+    return fract(sin(dot(p, vec2<f32>(12.9898, 78.233))) * 43758.5453);
+}
+
+// Noise that changes smoothly between neighboring blocks
+fn noise(p: vec2<f32>) -> f32 {
+    let i = floor(p);
+    let f = fract(p);
+    let u = f * f * (3.0 - 2.0 * f);
+    let bottom = mix(hash(i), hash(i + vec2<f32>(1.0, 0.0)), u.x);
+    let top = mix(hash(i + vec2<f32>(0.0, 1.0)), hash(i + vec2<f32>(1.0, 1.0)), u.x);
+    return mix(bottom, top, u.y);
+}
+
+fn material_id(texel: vec4<f32>) -> i32 {
+    return i32(round(texel.r * 255.0));
+}
+
+// The color of a block of the given type at a position in the world
+fn shade(id: i32, seed: f32, position: vec2<f32>) -> vec4<f32> {
+    let color1 = textureLoad(materials, vec2<i32>(id, 0), 0);
+    let color2 = textureLoad(materials, vec2<i32>(id, 1), 0);
+    let animation = i32(textureLoad(materials, vec2<i32>(id, 2), 0).x);
+    let time = globals.time;
+
+    var x = seed;
+    var brightness = 1.0;
+    if (animation == ANIMATION_FLICKER) {
+        // Jump to a new random color a few times a second, at different moments for each block
+        x = hash(position + floor(time * 12.0 + seed * 12.0));
+    } else if (animation == ANIMATION_SHIMMER) {
+        brightness = 1.0 + 0.15 * sin(time * 2.0 + position.x * 0.4 + position.y * 0.25 + seed);
+    } else if (animation == ANIMATION_DRIFT) {
+        x = mix(seed, noise((position - vec2<f32>(time, time * 4.0)) * 0.25), 0.7);
+    }
+    let color = mix(color2, color1, x);
+    return vec4<f32>(color.rgb * brightness, color.a);
+}
+
+// Whether the block at an offset from a pixel is of the given type - neighbors in other chunks are
+// in the border of the chunk texture
+fn same_material(pixel: vec2<i32>, offset: vec2<i32>, id: i32) -> bool {
+    return material_id(textureLoad(blocks, pixel + offset, 0)) == id;
+}
+
+// The color of a block drawn with its tile, shaped by the autotile pieces. Each quarter of the
+// block is taken from the piece matching the neighbors on its side: the one beside it, the one
+// above or below it, and the one diagonally between them.
+fn autotile(id: i32, pixel: vec2<i32>, world_position: vec2<f32>) -> vec4<f32> {
+    let tile = textureLoad(materials, vec2<i32>(id, 3), 0);
+    // The position inside the tile, with the tileset's rows going down
+    let position_in_tile = fract(world_position / TILE_BLOCKS);
+    let in_tile = min(
+        vec2<i32>(vec2<f32>(position_in_tile.x, 1.0 - position_in_tile.y) * f32(TILE_TEXELS)),
+        vec2<i32>(TILE_TEXELS - 1),
+    );
+    let texel = vec2<i32>(i32(tile.y), i32(tile.z)) * TILE_TEXELS + in_tile;
+    let color = textureLoad(tileset, texel, 0);
+
+    // The quarter of the block this is in, with rows of the chunk texture going down too
+    let p = fract(world_position);
+    let side = vec2<i32>(select(-1, 1, p.x >= 0.5), select(1, -1, p.y >= 0.5));
+    let beside = same_material(pixel, vec2<i32>(side.x, 0), id);
+    let above_or_below = same_material(pixel, vec2<i32>(0, side.y), id);
+    let diagonal = same_material(pixel, side, id);
+    var piece = PIECE_FILL;
+    if (!beside && !above_or_below) {
+        piece = PIECE_OUTER_CORNERS;
+    } else if (!above_or_below) {
+        piece = PIECE_HORIZONTAL_EDGES;
+    } else if (!beside) {
+        piece = PIECE_VERTICAL_EDGES;
+    } else if (!diagonal) {
+        piece = PIECE_INNER_CORNERS;
+    }
+
+    let in_piece = min(
+        vec2<i32>(vec2<f32>(p.x, 1.0 - p.y) * f32(PIECE_TEXELS)),
+        vec2<i32>(PIECE_TEXELS - 1),
+    );
+    let shape = textureLoad(pieces, vec2<i32>(piece * PIECE_TEXELS, 0) + in_piece, 0);
+    return color * shape;
+}
+
+[[stage(fragment)]]
+fn fragment(in: FragmentInput) -> [[location(0)]] vec4<f32> {
+    // The chunk is drawn from the texture inside its border
+    let size = textureDimensions(blocks) - vec2<i32>(2 * TEXTURE_BORDER);
+    let pixel = min(vec2<i32>(in.uv * vec2<f32>(size)), size - vec2<i32>(1));
+    let pixel = pixel + vec2<i32>(TEXTURE_BORDER);
+    let position = floor(in.world_position.xy);
+
+    let texel = textureLoad(blocks, pixel, 0);
+    let id = material_id(texel);
+    let burning_material = i32(globals.burning_material);
+    var color = shade(id, texel.g, position);
+    if (globals.autotile != 0u && textureLoad(materials, vec2<i32>(id, 3), 0).x > 0.0) {
+        color = autotile(id, pixel, in.world_position.xy);
+    }
+    if (texel.b > 0.0) {
+        color = mix(color, shade(burning_material, texel.g, position), texel.b);
+    }
+
+    // Blocks darken without light, and so does the background behind anything transparent
+    let light = texel.a;
+    color = vec4<f32>(color.rgb * mix(MIN_LIGHT, 1.0, light), color.a);
+    let shadow = (1.0 - light) * SHADOW_OPACITY;
+    let shaded_alpha = color.a + shadow * (1.0 - color.a);
+    color = vec4<f32>(color.rgb * color.a / max(shaded_alpha, 0.001), shaded_alpha);
+
+    // Find the strongest glow reaching this block, including from blocks in other chunks
+    var glow = vec4<f32>(0.0);
+    for (var dx: i32 = -GLOW_RADIUS; dx <= GLOW_RADIUS; dx = dx + 1) {
+        for (var dy: i32 = -GLOW_RADIUS; dy <= GLOW_RADIUS; dy = dy + 1) {
+            let other = pixel + vec2<i32>(dx, dy);
+            let other_texel = textureLoad(blocks, other, 0);
+            var id = material_id(other_texel);
+            var amount = textureLoad(materials, vec2<i32>(id, 2), 0).y;
+            if (other_texel.b > amount) {
+                id = burning_material;
+                amount = other_texel.b;
+            }
+            let distance = length(vec2<f32>(f32(dx), f32(dy)));
+            let strength = amount * (1.0 - distance / f32(GLOW_RADIUS + 1));
+            if (strength > glow.a) {
+                glow = vec4<f32>(textureLoad(materials, vec2<i32>(id, 0), 0).rgb, strength);
+            }
+        }
+    }
+
+    // Glowing light brightens what is here and shows up over anything transparent
+    let glow_alpha = glow.a * GLOW_STRENGTH;
+    let lit = color.rgb + glow.rgb * glow_alpha * 0.5;
+    let alpha = color.a + glow_alpha * (1.0 - color.a);
+    let rgb = (lit * color.a + glow.rgb * glow_alpha * (1.0 - color.a)) / max(alpha, 0.001);
+    return vec4<f32>(rgb, alpha);
+}
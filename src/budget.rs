@@ -0,0 +1,152 @@
+use crate::chemistry::Property::*;
+use crate::spells::*;
+use std::f32::consts::PI;
+
+/// Mana every cast of a spell costs, before it does anything
+pub(crate) const CAST_COST: f32 = 1.0;
+
+/// Mana it costs to change the material of a target
+const MATERIAL_COST: f32 = 1.0;
+
+/// Mana it costs to change any other property of a target
+const PROPERTY_COST: f32 = 0.5;
+
+/// Mana it costs to summon a new entity
+const SUMMON_COST: f32 = 10.0;
+
+/// How many entities are assumed to touch each target, on top of its 8 adjacent blocks
+const ADJACENT_ENTITIES: f32 = 1.0;
+
+/// The fraction of targets a filter like Is or Not is assumed to keep
+const FILTER_FRACTION: f32 = 0.5;
+
+/// What a spell is expected to do each time it's cast from a single target
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct SpellEstimate {
+    /// The most targets its effects can reach
+    pub(crate) max_targets: f32,
+    /// How many targets its effects are expected to reach
+    pub(crate) expected_targets: f32,
+    /// The mana it's expected to take, including the cost of casting it
+    pub(crate) cost: f32,
+}
+
+/// The most and the expected number of targets a selector chooses for each target given to it
+fn fan_out(selector: &SpellSelector) -> (f32, f32) {
+    let disc = |radius: f32| PI * radius * radius;
+    match selector {
+        SpellSelector::Adjacent => (8.0 + ADJACENT_ENTITIES, 8.0 + ADJACENT_ENTITIES),
+        SpellSelector::Area(radius) => (disc(radius + 1.0), disc(*radius)),
+        // Sight is blocked by solid blocks, so it usually reaches less than the whole area
+        SpellSelector::Sight(radius) => (disc(radius + 1.0), FILTER_FRACTION * disc(*radius)),
+        SpellSelector::Ray(_, length) | SpellSelector::Aimed(length) => {
+            (length.ceil() + 1.0, *length)
+        }
+        SpellSelector::AimedAt | SpellSelector::Conducting => (1.0, 1.0),
+        SpellSelector::Is(_)
+        | SpellSelector::Threshold(_, _)
+        | SpellSelector::Not(_)
+        | SpellSelector::Quantify(_, _, _) => (1.0, FILTER_FRACTION),
+        SpellSelector::Bind(left, right) => {
+            let (left_max, left_expected) = fan_out(left);
+            let (right_max, right_expected) = fan_out(right);
+            (left_max * right_max, left_expected * right_expected)
+        }
+    }
+}
+
+/// The mana it takes to apply an effect to a single target
+pub(crate) fn effect_cost(effect: &SpellEffect) -> f32 {
+    match effect {
+        SpellEffect::Summon => SUMMON_COST,
+        SpellEffect::Add(Material(_))
+        | SpellEffect::Send(Material(_))
+        | SpellEffect::Remove(Material(_))
+        | SpellEffect::Receive(Material(_)) => MATERIAL_COST,
+        SpellEffect::Add(Dynamic(_))
+        | SpellEffect::Send(Dynamic(_))
+        | SpellEffect::Remove(Dynamic(_))
+        | SpellEffect::Receive(Dynamic(_)) => PROPERTY_COST,
+        // Removing a static property removes the material that provides it
+        SpellEffect::Remove(Static(_)) => MATERIAL_COST,
+        // Static properties can't be given directly, so these do nothing
+        SpellEffect::Add(Static(_))
+        | SpellEffect::Send(Static(_))
+        | SpellEffect::Receive(Static(_)) => 0.0,
+    }
+}
+
+impl Spell {
+    /// Work out how much the spell does, without casting it
+    pub(crate) fn estimate(&self) -> SpellEstimate {
+        let (max_targets, expected_targets, cost) = self.estimate_from(1.0, 1.0);
+        SpellEstimate {
+            max_targets,
+            expected_targets,
+            cost: CAST_COST + cost,
+        }
+    }
+
+    /// The most and expected targets reached and the expected cost of the effects, given how many
+    /// targets reach this part of the spell
+    fn estimate_from(&self, max_targets: f32, expected_targets: f32) -> (f32, f32, f32) {
+        match self {
+            Spell::Effects(effects) => {
+                let cost: f32 = effects.iter().map(effect_cost).sum();
+                (max_targets, expected_targets, expected_targets * cost)
+            }
+            Spell::Select(selector, spell) => {
+                let (max, expected) = fan_out(selector);
+                spell.estimate_from(max_targets * max, expected_targets * expected)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blocks::*;
+    use crate::chemistry::DynamicProperty::*;
+
+    #[test]
+    fn adjacent_multiplies_the_targets() {
+        let once = basic(
+            [SpellSelector::Adjacent],
+            [SpellEffect::Add(Material(*WATER))],
+        );
+        let twice = basic(
+            [SpellSelector::Adjacent, SpellSelector::Adjacent],
+            [SpellEffect::Add(Material(*WATER))],
+        );
+        assert_eq!(once.estimate().max_targets, 9.0);
+        assert_eq!(twice.estimate().max_targets, 81.0);
+        assert_eq!(once.estimate().cost, CAST_COST + 9.0 * MATERIAL_COST);
+        assert!(twice.estimate().cost > once.estimate().cost);
+    }
+
+    #[test]
+    fn filters_lower_the_expected_targets() {
+        let spell = basic(
+            [SpellSelector::Adjacent, SpellSelector::Is(Material(*AIR))],
+            [SpellEffect::Send(Dynamic(Burning))],
+        );
+        let estimate = spell.estimate();
+        assert_eq!(estimate.max_targets, 9.0);
+        assert_eq!(estimate.expected_targets, 4.5);
+        assert_eq!(estimate.cost, CAST_COST + 4.5 * PROPERTY_COST);
+    }
+
+    #[test]
+    fn every_built_in_spell_has_a_finite_estimate() {
+        for rule in NATURAL_RULES.iter().chain(PLAYER_RULES.iter()) {
+            let estimate = rule.spell.estimate();
+            assert!(estimate.cost.is_finite(), "{}", rule.name);
+            assert!(
+                estimate.expected_targets <= estimate.max_targets,
+                "{}",
+                rule.name
+            );
+        }
+    }
+}
@@ -2,8 +2,11 @@ use crate::blocks::*;
 use crate::chemistry::DynamicProperty::*;
 use crate::chemistry::Property::*;
 use crate::chemistry::*;
+use bevy::math::Vec2;
 use bevy::prelude::Image;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
 use rand::seq::SliceRandom;
+use rand::Rng;
 
 /// The size of the whole grid of blocks
 pub(crate) const GRID_SIZE: usize = 256;
@@ -35,13 +38,135 @@ impl BlockGrid {
     }
 }
 
-pub(crate) fn neighbors_shuffle<I1, I2>(x: i32, y: i32, xs: I1, ys: I2) -> Vec<(i32, i32)>
+/// The size of the square chunks the grid is split into to track activity
+pub(crate) const CHUNK_SIZE: usize = 16;
+
+/// The number of chunks along each side of the grid
+const CHUNKS: usize = GRID_SIZE / CHUNK_SIZE;
+
+/// A rectangle of blocks, including its edges
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) struct Rect {
+    pub(crate) x_min: i32,
+    pub(crate) y_min: i32,
+    pub(crate) x_max: i32,
+    pub(crate) y_max: i32,
+}
+
+impl Rect {
+    fn union(&self, other: &Rect) -> Rect {
+        Rect {
+            x_min: self.x_min.min(other.x_min),
+            y_min: self.y_min.min(other.y_min),
+            x_max: self.x_max.max(other.x_max),
+            y_max: self.y_max.max(other.y_max),
+        }
+    }
+
+    fn intersection(&self, other: &Rect) -> Option<Rect> {
+        let rect = Rect {
+            x_min: self.x_min.max(other.x_min),
+            y_min: self.y_min.max(other.y_min),
+            x_max: self.x_max.min(other.x_max),
+            y_max: self.y_max.min(other.y_max),
+        };
+        if rect.x_min <= rect.x_max && rect.y_min <= rect.y_max {
+            Some(rect)
+        } else {
+            None
+        }
+    }
+
+    fn blocks(&self) -> impl Iterator<Item = (i32, i32)> {
+        let Rect {
+            x_min,
+            y_min,
+            x_max,
+            y_max,
+        } = *self;
+        (x_min..=x_max).flat_map(move |x| (y_min..=y_max).map(move |y| (x, y)))
+    }
+}
+
+/// Tracks the region of each chunk where blocks might move - chunks with no such region are
+/// asleep and skipped by physics
+#[derive(Default)]
+pub(crate) struct ChunkActivity {
+    /// The dirty rectangle of each chunk this step, or None if the chunk is asleep
+    current: Vec<Option<Rect>>,
+    /// The dirty rectangles being collected for the next step
+    next: Vec<Option<Rect>>,
+}
+
+impl ChunkActivity {
+    /// Wake up a block and its neighbors for the next step
+    pub(crate) fn wake(&mut self, x: i32, y: i32) {
+        if self.next.is_empty() {
+            self.next = vec![None; CHUNKS * CHUNKS];
+        }
+        let grid = Rect {
+            x_min: 0,
+            y_min: 0,
+            x_max: GRID_SIZE as i32 - 1,
+            y_max: GRID_SIZE as i32 - 1,
+        };
+        let area = Rect {
+            x_min: x - 1,
+            y_min: y - 1,
+            x_max: x + 1,
+            y_max: y + 1,
+        };
+        let area = match area.intersection(&grid) {
+            Some(area) => area,
+            None => return,
+        };
+
+        let chunk_size = CHUNK_SIZE as i32;
+        for cx in area.x_min / chunk_size..=area.x_max / chunk_size {
+            for cy in area.y_min / chunk_size..=area.y_max / chunk_size {
+                let chunk = Rect {
+                    x_min: cx * chunk_size,
+                    y_min: cy * chunk_size,
+                    x_max: (cx + 1) * chunk_size - 1,
+                    y_max: (cy + 1) * chunk_size - 1,
+                };
+                if let Some(area) = area.intersection(&chunk) {
+                    let dirty = &mut self.next[(cy * CHUNKS as i32 + cx) as usize];
+                    *dirty = Some(dirty.map_or(area, |d| d.union(&area)));
+                }
+            }
+        }
+    }
+
+    /// Start a new step, using the regions woken up during the last one
+    pub(crate) fn advance(&mut self) {
+        self.current = std::mem::take(&mut self.next);
+    }
+
+    /// The number of chunks that are awake this step
+    pub(crate) fn awake_chunks(&self) -> usize {
+        self.current.iter().flatten().count()
+    }
+
+    /// Every block in the dirty rectangles of this step
+    pub(crate) fn awake_blocks(&self) -> impl Iterator<Item = (i32, i32)> + '_ {
+        self.current.iter().flatten().flat_map(|rect| rect.blocks())
+    }
+}
+
+pub(crate) fn neighbors_shuffle<I1, I2>(
+    x: i32,
+    y: i32,
+    xs: I1,
+    ys: I2,
+    rng: &mut impl Rng,
+) -> Vec<(i32, i32)>
 where
     I1: IntoIterator<Item = i32>,
     I2: IntoIterator<Item = i32> + Clone,
 {
     let mut r = neighbors(x, y, xs, ys).collect::<Vec<_>>();
-    r.shuffle(&mut rand::thread_rng());
+    r.shuffle(rng);
     r
 }
 
@@ -63,22 +188,141 @@ where
     })
 }
 
-pub(crate) fn update_texture_pixel(info: &WorldInfo, texture: &mut Image, x: i32, y: i32) {
-    let block = info.get_block(x, y).unwrap();
-    let mut color = block.color();
+/// Iterate over the blocks crossed by a line segment, in order, along with the distance from the
+/// start at which each block is entered. The block containing the start is skipped.
+pub(crate) fn blocks_on_line(start: Vec2, end: Vec2) -> impl Iterator<Item = (i32, i32, f32)> {
+    let length = start.distance(end);
+    let dir = (end - start).normalize_or_zero();
+    let step_x = if dir.x > 0.0 { 1 } else { -1 };
+    let step_y = if dir.y > 0.0 { 1 } else { -1 };
+    let (mut x, mut y) = (start.x.floor() as i32, start.y.floor() as i32);
 
-    // Draw all burning blocks as fire
-    if info.get(Target::Block(x, y), Dynamic(Burning)) > 0.0 {
-        let x = rand::random::<f32>();
-        let fire_data = ALL_BLOCK_DATA.get(*FIRE as usize).unwrap();
-        color = fire_data.color1 * x + fire_data.color2 * (1.0 - x);
-    }
+    // The distance along the line until the next x and y block boundaries are crossed
+    let first_crossing = |pos: f32, dir: f32| {
+        if dir > 0.0 {
+            (pos.floor() + 1.0 - pos) / dir
+        } else if dir < 0.0 {
+            (pos - pos.floor()) / -dir
+        } else {
+            f32::INFINITY
+        }
+    };
+    let mut next_x = first_crossing(start.x, dir.x);
+    let mut next_y = first_crossing(start.y, dir.y);
+    let delta_x = 1.0 / dir.x.abs();
+    let delta_y = 1.0 / dir.y.abs();
+
+    std::iter::from_fn(move || {
+        let t = if next_x < next_y {
+            x += step_x;
+            next_x += delta_x;
+            next_x - delta_x
+        } else {
+            y += step_y;
+            next_y += delta_y;
+            next_y - delta_y
+        };
+        if t <= length {
+            Some((x, y, t))
+        } else {
+            None
+        }
+    })
+}
+
+/// The size of the square textures the grid is drawn with - only the textures of chunks with
+/// changed blocks need to be uploaded again
+pub(crate) const TEXTURE_CHUNK_SIZE: usize = 64;
+
+/// The number of texture chunks along each side of the grid
+pub(crate) const TEXTURE_CHUNKS: usize = GRID_SIZE / TEXTURE_CHUNK_SIZE;
 
-    let i = 4 * (x as usize + (GRID_SIZE - y as usize - 1) * GRID_SIZE);
-    texture.data.splice(
-        i..i + 4,
-        color.as_rgba_f32().iter().map(|&v| (v * 255.0) as u8),
+/// How many blocks past the edges of its chunk a chunk texture also holds, so that the block shader
+/// can look at neighbors in other chunks - must match `TEXTURE_BORDER` in blocks.wgsl, and reach
+/// as far as the shader looks
+pub(crate) const TEXTURE_BORDER: usize = 3;
+
+/// The width and height of a chunk texture, including its border
+const TEXTURE_SIZE: usize = TEXTURE_CHUNK_SIZE + 2 * TEXTURE_BORDER;
+
+/// The lower left block of a texture chunk
+pub(crate) fn texture_chunk_origin(index: usize) -> (i32, i32) {
+    let x = index % TEXTURE_CHUNKS * TEXTURE_CHUNK_SIZE;
+    let y = index / TEXTURE_CHUNKS * TEXTURE_CHUNK_SIZE;
+    (x as i32, y as i32)
+}
+
+/// The texture chunks holding a block, either as one of their own blocks or in their border
+pub(crate) fn texture_chunks_holding(x: i32, y: i32) -> impl Iterator<Item = usize> {
+    let (size, border) = (TEXTURE_CHUNK_SIZE as i32, TEXTURE_BORDER as i32);
+    let chunks =
+        move |v: i32| (v - border).max(0) / size..=(v + border).min(GRID_SIZE as i32 - 1) / size;
+    let rows = chunks(y);
+    chunks(x).flat_map(move |column| {
+        rows.clone()
+            .map(move |row| row as usize * TEXTURE_CHUNKS + column as usize)
+    })
+}
+
+/// Where a block is stored in the data of a chunk texture holding it, with rows going from the top
+/// of the texture down
+fn texel_offset(index: usize, x: i32, y: i32) -> usize {
+    let (x0, y0) = texture_chunk_origin(index);
+    let local_x = (x - x0 + TEXTURE_BORDER as i32) as usize;
+    let local_y = (y - y0 + TEXTURE_BORDER as i32) as usize;
+    4 * (local_x + (TEXTURE_SIZE - local_y - 1) * TEXTURE_SIZE)
+}
+
+/// Create the texture for a chunk, drawing every block in it and its border. The border past the
+/// edges of the grid is left as air.
+pub(crate) fn new_chunk_texture(info: &WorldInfo, index: usize) -> Image {
+    let mut texture = Image::new_fill(
+        Extent3d {
+            width: TEXTURE_SIZE as u32,
+            height: TEXTURE_SIZE as u32,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[0, 0, 0, 0],
+        TextureFormat::Rgba8Unorm,
     );
+    let (x0, y0) = texture_chunk_origin(index);
+    let (size, border) = (TEXTURE_CHUNK_SIZE as i32, TEXTURE_BORDER as i32);
+    let blocks = |v0: i32| (v0 - border).max(0)..(v0 + size + border).min(GRID_SIZE as i32);
+    for x in blocks(x0) {
+        for y in blocks(y0) {
+            update_texture_pixel(info, &mut texture, index, x, y);
+        }
+    }
+    texture
+}
+
+/// Draw a block into the texture of a chunk holding it. The texture doesn't hold colors but what
+/// the block shader needs to color the block: the material id, the color seed, how much the block
+/// is burning, and the light reaching it.
+pub(crate) fn update_texture_pixel(
+    info: &WorldInfo,
+    texture: &mut Image,
+    index: usize,
+    x: i32,
+    y: i32,
+) {
+    let block = info.get_block(x, y).unwrap();
+    let burning = info
+        .get(Target::Block(x, y), Dynamic(Burning))
+        .clamp(0.0, 1.0);
+    let light = info
+        .get(Target::Block(x, y), Static(StaticProperty::Light))
+        .clamp(0.0, 1.0);
+    let pixel = [
+        block.id as u8,
+        block.color_seed(),
+        (burning * 255.0) as u8,
+        (light * 255.0) as u8,
+    ];
+
+    let i = texel_offset(index, x, y);
+    texture.data[i..i + 4].copy_from_slice(&pixel);
 }
 
 pub(crate) fn set_block_range<I1, I2>(info: &mut WorldInfo, xs: I1, ys: I2, id: u16)
@@ -103,3 +347,82 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The material id, color seed, burning and light stored for a block in a chunk texture
+    fn texel(texture: &Image, index: usize, x: i32, y: i32) -> &[u8] {
+        let i = texel_offset(index, x, y);
+        &texture.data[i..i + 4]
+    }
+
+    #[test]
+    fn chunk_textures_hold_their_own_blocks() {
+        let mut info = WorldInfo::default();
+        set_block_range(&mut info, 70..71, 32..33, *STONE);
+        let chunks: Vec<usize> = texture_chunks_holding(70, 32).collect();
+        assert_eq!(chunks, vec![1]);
+
+        // Rows are stored from the top of the chunk down
+        let texture = new_chunk_texture(&info, 1);
+        let size = TEXTURE_SIZE as i32;
+        assert_eq!(texture.data.len(), 4 * (size * size) as usize);
+        let i = 4 * (9 + (size - 36) * size) as usize;
+        assert_eq!(texture.data[i], *STONE as u8);
+        assert_eq!(texel(&texture, 1, 70, 32)[0], *STONE as u8);
+        let not_air = texture.data.chunks(4).filter(|p| p[0] != *AIR as u8);
+        assert_eq!(not_air.count(), 1);
+    }
+
+    #[test]
+    fn blocks_near_chunk_edges_are_held_by_every_chunk_reaching_them() {
+        let mut info = WorldInfo::default();
+        let edge = TEXTURE_CHUNK_SIZE as i32;
+        set_block_range(&mut info, edge..edge + 1, edge - 1..edge, *STONE);
+        let mut chunks: Vec<usize> = texture_chunks_holding(edge, edge - 1).collect();
+        chunks.sort_unstable();
+        let above = TEXTURE_CHUNKS;
+        assert_eq!(chunks, vec![0, 1, above, above + 1]);
+
+        // Both sides of the edge between two chunks see the blocks across it
+        for index in chunks {
+            let texture = new_chunk_texture(&info, index);
+            assert_eq!(texel(&texture, index, edge, edge - 1)[0], *STONE as u8);
+            assert_eq!(texel(&texture, index, edge - 1, edge - 1)[0], *AIR as u8);
+        }
+        assert_eq!(texture_chunks_holding(0, 0).collect::<Vec<_>>(), vec![0]);
+    }
+
+    #[test]
+    fn burning_is_stored_with_the_block() {
+        let mut info = WorldInfo::default();
+        set_block_range(&mut info, 3..4, 4..5, *WOOD);
+        info.set(Target::Block(3, 4), Burning, 1.0);
+
+        let texture = new_chunk_texture(&info, 0);
+        assert_eq!(texel(&texture, 0, 3, 4)[0], *WOOD as u8);
+        assert_eq!(texel(&texture, 0, 3, 4)[2], 255);
+    }
+
+    #[test]
+    fn light_is_stored_with_the_block() {
+        let mut info = WorldInfo::default();
+        set_block_range(&mut info, 0..GRID_SIZE as i32, 20..21, *STONE);
+        info.update_light();
+
+        let texture = new_chunk_texture(&info, 0);
+        // Lit by the sky, and hidden from it under the stone
+        assert!(texel(&texture, 0, 3, 21)[3] > 0);
+        assert_eq!(texel(&texture, 0, 3, 5)[3], 0);
+
+        // Blocks whose light changes are drawn again
+        info.reset_changes();
+        set_block_range(&mut info, 3..4, 20..21, *AIR);
+        info.update_light();
+        assert!(info
+            .all_changed()
+            .any(|target| target == Target::Block(3, 5)));
+    }
+}
@@ -1,19 +1,25 @@
 use crate::{
-    blocks::{Block, PhysicsFlags},
-    cells::BlockGrid,
+    blocks::{Block, BlockPhysics, PhysicsFlags, ALL_BLOCK_DATA},
+    cells::{BlockGrid, ChunkActivity, GRID_SIZE},
+    electricity::ElectricField,
+    light::LightField,
+    spatial::EntityColliders,
 };
 use bevy::{
     math::Vec2,
     prelude::{Component, Entity},
-    utils::{HashMap, HashSet},
+    utils::{HashMap, HashSet, StableHashSet},
 };
+use rand::{rngs::StdRng, SeedableRng};
 use Property::*;
 use Target::*;
 
-#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub(crate) enum Target {
     Block(i32, i32),
     Entity(Entity),
+    /// One cell of an entity split into `BodyParts`, numbered row by row from the bottom left
+    Part(Entity, u16),
 }
 
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
@@ -27,6 +33,10 @@ pub(crate) enum Property {
 pub(crate) enum DynamicProperty {
     Mana(ManaId),
     Burning,
+    /// Gives off light
+    Bright,
+    /// Holds connected conductive targets at an electric potential
+    Charged,
     Forwards,
 }
 
@@ -34,12 +44,18 @@ pub(crate) enum DynamicProperty {
 pub(crate) enum StaticProperty {
     IsEntity,
     Liquid,
+    /// The amount of light reaching a target
+    Light,
+    /// The electric potential of a target
+    Electric,
+    /// How easily fire spreads to a target
+    Flammable,
 }
 
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub(crate) struct ManaId(pub(crate) u8);
 
-#[derive(Default)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub(crate) struct AABBCollider {
     pub(crate) ll: Vec2,
     pub(crate) ur: Vec2,
@@ -53,31 +69,167 @@ impl AABBCollider {
             && self.ur.y >= other.ll.y
     }
 
+    /// The smallest distance between any two points of the colliders
+    pub(crate) fn distance(&self, other: &AABBCollider) -> f32 {
+        let gap = (self.ll - other.ur).max(other.ll - self.ur).max(Vec2::ZERO);
+        gap.length()
+    }
+
+    /// The distance along a ray at which it enters the collider, if it does so within max_t
+    pub(crate) fn ray_hit(&self, origin: Vec2, dir: Vec2, max_t: f32) -> Option<f32> {
+        let mut t_enter = 0.0f32;
+        let mut t_exit = max_t;
+        for (origin, dir, ll, ur) in [
+            (origin.x, dir.x, self.ll.x, self.ur.x),
+            (origin.y, dir.y, self.ll.y, self.ur.y),
+        ] {
+            if dir == 0.0 {
+                if origin < ll || origin > ur {
+                    return None;
+                }
+            } else {
+                let t1 = (ll - origin) / dir;
+                let t2 = (ur - origin) / dir;
+                t_enter = t_enter.max(t1.min(t2));
+                t_exit = t_exit.min(t1.max(t2));
+            }
+        }
+        if t_enter <= t_exit {
+            Some(t_enter)
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn from_point(point: Vec2) -> AABBCollider {
+        AABBCollider {
+            ll: point,
+            ur: point,
+        }
+    }
+
     pub(crate) fn from_block(x: i32, y: i32) -> AABBCollider {
         AABBCollider {
             ll: Vec2::new(x as f32, y as f32),
             ur: Vec2::new((x + 1) as f32, (y + 1) as f32),
         }
     }
+
+    /// The block at the center of the collider
+    fn center_block(&self) -> (i32, i32) {
+        let center = (self.ll + self.ur) / 2.0;
+        (center.x.floor() as i32, center.y.floor() as i32)
+    }
 }
 
 #[derive(Component)]
 pub(crate) struct ChemEntity;
 
+/// Splits a `ChemEntity` into a grid of parts that each have their own properties, so that e.g.
+/// only the side touching a fire catches at first. Blocks only touch the entity through its
+/// parts.
+#[derive(Clone, Copy, Component, Debug, Eq, PartialEq)]
+pub(crate) struct BodyParts {
+    pub(crate) columns: u8,
+    pub(crate) rows: u8,
+}
+
+impl BodyParts {
+    pub(crate) fn count(&self) -> u16 {
+        self.columns as u16 * self.rows as u16
+    }
+
+    /// The column and row of a part
+    pub(crate) fn cell(&self, part: u16) -> (u8, u8) {
+        let columns = self.columns as u16;
+        ((part % columns) as u8, (part / columns) as u8)
+    }
+
+    /// The part in a column and row, if there is one
+    pub(crate) fn part(&self, column: i32, row: i32) -> Option<u16> {
+        if column >= 0 && column < self.columns as i32 && row >= 0 && row < self.rows as i32 {
+            Some(row as u16 * self.columns as u16 + column as u16)
+        } else {
+            None
+        }
+    }
+
+    /// The region of an entity's collider a part covers
+    pub(crate) fn collider(&self, entity: &AABBCollider, part: u16) -> AABBCollider {
+        let (column, row) = self.cell(part);
+        let size = (entity.ur - entity.ll) / Vec2::new(self.columns as f32, self.rows as f32);
+        let ll = entity.ll + size * Vec2::new(column as f32, row as f32);
+        AABBCollider { ll, ur: ll + size }
+    }
+}
+
 // TODO: WorldInfo should be a resource, not a component.
-#[derive(Component, Default)]
+#[derive(Component)]
 pub(crate) struct WorldInfo {
     /// The grid of blocks in the world
     blocks: BlockGrid,
     /// The colliders of all entities in the world
     // TODO: Consider storing AABBCollider as component on the entity instead.
-    pub(crate) entity_colliders: HashMap<Entity, AABBCollider>,
+    pub(crate) entity_colliders: EntityColliders,
+    /// How the entities that are split into parts are split up
+    pub(crate) entity_parts: HashMap<Entity, BodyParts>,
+    /// The point in the grid each entity that is aiming its spells is aiming them at
+    pub(crate) aims: HashMap<Entity, Vec2>,
     /// Stores the value of every property on every target
     properties: HashMap<Target, HashMap<DynamicProperty, f32>>,
-    /// Stores a set of active targets with each property
-    active: HashMap<Property, HashSet<Target>>,
+    /// Stores a set of active targets with each property, hashed the same way every run so that
+    /// looking through them is repeatable
+    active: HashMap<Property, StableHashSet<Target>>,
     /// The set of targets that have changed so far this step
     changed: HashSet<Target>,
+    /// The regions of the grid where blocks might move
+    activity: ChunkActivity,
+    /// The light reaching every block
+    light: LightField,
+    /// The blocks that changed since the light was last worked out
+    unlit: HashSet<(i32, i32)>,
+    /// The electricity in every conductive target
+    pub(crate) electricity: ElectricField,
+    /// How many block-seconds of falling blocks each entity has been crushed by since it last
+    /// took damage for them
+    pub(crate) crushing: HashMap<Entity, f32>,
+    /// How many seconds have been simulated since entities last took damage
+    pub(crate) damage_time: f32,
+    /// The number of steps simulated so far
+    pub(crate) steps: u64,
+    /// The randomness rules are run with, which can be seeded to make the simulation repeatable
+    pub(crate) rng: StdRng,
+}
+
+impl Default for WorldInfo {
+    fn default() -> Self {
+        let blocks = BlockGrid::default();
+        let mut active: HashMap<Property, StableHashSet<Target>> = HashMap::default();
+        for x in 0..GRID_SIZE as i32 {
+            for y in 0..GRID_SIZE as i32 {
+                for p in blocks.get(x, y).unwrap().iter_properties() {
+                    active.entry(p).or_default().insert(Block(x, y));
+                }
+            }
+        }
+        Self {
+            blocks,
+            entity_colliders: Default::default(),
+            entity_parts: Default::default(),
+            aims: Default::default(),
+            properties: Default::default(),
+            active,
+            changed: Default::default(),
+            activity: Default::default(),
+            light: Default::default(),
+            unlit: Default::default(),
+            electricity: Default::default(),
+            crushing: Default::default(),
+            damage_time: 0.0,
+            steps: 0,
+            rng: StdRng::from_entropy(),
+        }
+    }
 }
 
 impl WorldInfo {
@@ -90,7 +242,7 @@ impl WorldInfo {
                     0.0
                 }
             }
-            (Entity(_), Material(_)) => 0.0,
+            (Entity(_) | Part(_, _), Material(_)) => 0.0,
             (target, Dynamic(property)) => self
                 .properties
                 .get(&target)
@@ -99,8 +251,24 @@ impl WorldInfo {
                 .unwrap_or_default(),
             (target, Static(property)) => match (target, property) {
                 (Block(_, _), StaticProperty::IsEntity) => 0.0,
-                (Entity(_), StaticProperty::IsEntity) => 1.0,
-                _ => todo!(),
+                (Entity(_) | Part(_, _), StaticProperty::IsEntity) => 1.0,
+                (Block(x, y), StaticProperty::Liquid) => {
+                    if self.get_block(x, y).unwrap().data().physics == BlockPhysics::Liquid {
+                        1.0
+                    } else {
+                        0.0
+                    }
+                }
+                (Entity(_) | Part(_, _), StaticProperty::Liquid) => 0.0,
+                (target, StaticProperty::Light) => {
+                    let (x, y) = self.target_block(target);
+                    self.light.get(x, y)
+                }
+                (target, StaticProperty::Electric) => self.electricity.get(target),
+                (Block(x, y), StaticProperty::Flammable) => {
+                    self.get_block(x, y).unwrap().data().flammability
+                }
+                (Entity(_) | Part(_, _), StaticProperty::Flammable) => 0.0,
             },
         }
     }
@@ -156,10 +324,10 @@ impl WorldInfo {
                 .insert(target1);
         }
 
-        for properties1 in properties1 {
+        if let Some(properties1) = properties1 {
             self.properties.insert(target2, properties1);
         }
-        for properties2 in properties2 {
+        if let Some(properties2) = properties2 {
             self.properties.insert(target1, properties2);
         }
     }
@@ -179,9 +347,154 @@ impl WorldInfo {
             }
             self.blocks.set(x, y, block);
             self.changed.insert(Block(x, y));
+            self.unlit.insert((x, y));
+            self.activity.wake(x, y);
         }
     }
 
+    /// Keep a block and its neighbors awake next step, even though they haven't changed
+    pub(crate) fn wake(&mut self, x: i32, y: i32) {
+        self.activity.wake(x, y);
+    }
+
+    /// Start a new step of activity tracking, putting to sleep every chunk that nothing woke
+    pub(crate) fn advance_activity(&mut self) {
+        self.activity.advance();
+    }
+
+    /// The number of chunks that are awake this step
+    pub(crate) fn awake_chunks(&self) -> usize {
+        self.activity.awake_chunks()
+    }
+
+    /// Work out the light reaching every block again, from the sky, bright targets, and glowing
+    /// blocks
+    pub(crate) fn update_light(&mut self) {
+        let sources = self.light_sources();
+        self.unlit.clear();
+        let changed = self.light.update(&self.blocks, sources);
+        self.record_light_changes(changed);
+    }
+
+    /// Work out the light again around the blocks that changed since it was last worked out, like
+    /// `update_light` does everywhere
+    pub(crate) fn update_changed_light(&mut self) {
+        let sources = self.light_sources();
+        let unlit = std::mem::take(&mut self.unlit);
+        let changed = self.light.update_around(&self.blocks, sources, unlit);
+        self.record_light_changes(changed);
+    }
+
+    /// The blocks light comes out of, and how brightly
+    fn light_sources(&self) -> Vec<(i32, i32, f32)> {
+        let bright = self
+            .active_matching(Dynamic(DynamicProperty::Bright))
+            .map(|target| {
+                let (x, y) = self.target_block(target);
+                (x, y, self.get(target, Dynamic(DynamicProperty::Bright)))
+            });
+        let glowing = ALL_BLOCK_DATA
+            .iter()
+            .enumerate()
+            .filter(|(_, data)| data.glow > 0.0)
+            .flat_map(|(id, data)| {
+                self.active_matching(Material(id as u16))
+                    .filter_map(move |target| match target {
+                        Block(x, y) => Some((x, y, data.glow)),
+                        _ => None,
+                    })
+            });
+        bright.chain(glowing).collect()
+    }
+
+    fn record_light_changes(&mut self, changed: Vec<(i32, i32)>) {
+        for (x, y) in changed {
+            let lit = self.light.get(x, y) > 0.0;
+            let active = self
+                .active
+                .entry(Static(StaticProperty::Light))
+                .or_default();
+            if lit {
+                active.insert(Block(x, y));
+            } else {
+                active.remove(&Block(x, y));
+            }
+            self.changed.insert(Block(x, y));
+        }
+    }
+
+    /// The block at the center of an entity
+    pub(crate) fn entity_block(&self, entity: Entity) -> (i32, i32) {
+        self.target_block(Entity(entity))
+    }
+
+    /// The block a target is in - the block at the center of its collider, for entities and parts
+    fn target_block(&self, target: Target) -> (i32, i32) {
+        match target {
+            Block(x, y) => (x, y),
+            _ => self
+                .collider(target)
+                .map_or((-1, -1), |collider| collider.center_block()),
+        }
+    }
+
+    /// The collider of an entity or a part of one
+    pub(crate) fn collider(&self, target: Target) -> Option<AABBCollider> {
+        match target {
+            Block(_, _) => None,
+            Entity(entity) => self.entity_colliders.get(&entity).cloned(),
+            Part(entity, part) => {
+                let collider = self.entity_colliders.get(&entity)?;
+                let parts = self.entity_parts.get(&entity)?;
+                Some(parts.collider(collider, part))
+            }
+        }
+    }
+
+    /// The parts an entity is split into, if any
+    pub(crate) fn parts<'a>(&'a self, entity: Entity) -> impl Iterator<Item = Target> + 'a {
+        let count = self
+            .entity_parts
+            .get(&entity)
+            .map_or(0, |parts| parts.count());
+        (0..count).map(move |part| Part(entity, part))
+    }
+
+    /// Forget an entity that no longer exists, along with its parts and all of their properties
+    pub(crate) fn remove_entity(&mut self, entity: Entity) {
+        let targets: Vec<Target> = std::iter::once(Entity(entity))
+            .chain(self.parts(entity))
+            .collect();
+        for target in targets {
+            for (property, _) in self.properties.remove(&target).into_iter().flatten() {
+                self.active
+                    .entry(Dynamic(property))
+                    .or_default()
+                    .remove(&target);
+            }
+        }
+        self.entity_colliders.remove(&entity);
+        self.entity_parts.remove(&entity);
+        self.aims.remove(&entity);
+        self.crushing.remove(&entity);
+    }
+
+    /// The point a target is aiming its spells at, if it's aiming them - parts aim wherever their
+    /// entity does
+    pub(crate) fn aim(&self, target: Target) -> Option<Vec2> {
+        match target {
+            Block(_, _) => None,
+            Entity(entity) | Part(entity, _) => self.aims.get(&entity).cloned(),
+        }
+    }
+
+    /// Every entity and every part of one
+    pub(crate) fn entity_targets<'a>(&'a self) -> impl Iterator<Item = Target> + 'a {
+        self.entity_colliders
+            .keys()
+            .flat_map(move |&entity| std::iter::once(Entity(entity)).chain(self.parts(entity)))
+    }
+
     pub(crate) fn all_changed<'a>(&'a self) -> impl Iterator<Item = Target> + 'a {
         self.changed.iter().cloned()
     }
@@ -194,7 +507,7 @@ impl WorldInfo {
                     block.set(PhysicsFlags::MOVED_THIS_STEP, false);
                     self.blocks.set(x, y, block);
                 }
-                Entity(_) => {}
+                Entity(_) | Part(_, _) => {}
             }
         }
         self.changed.clear()
@@ -204,10 +517,51 @@ impl WorldInfo {
         &'a self,
         property: Property,
     ) -> impl Iterator<Item = Target> + 'a {
+        let entities = if property == Static(StaticProperty::IsEntity) {
+            Some(self.entity_targets())
+        } else {
+            None
+        };
+        let electric = if property == Static(StaticProperty::Electric) {
+            Some(self.electricity.targets())
+        } else {
+            None
+        };
         self.active
             .get(&property)
             .into_iter()
             .flat_map(|x| x.iter())
             .cloned()
+            .chain(entities.into_iter().flatten())
+            .chain(electric.into_iter().flatten())
+    }
+
+    /// The number of targets active_matching would return
+    pub(crate) fn count_matching(&self, property: Property) -> usize {
+        if property == Static(StaticProperty::IsEntity) {
+            self.entity_targets().count()
+        } else if property == Static(StaticProperty::Electric) {
+            self.electricity.len()
+        } else {
+            self.active.get(&property).map_or(0, |x| x.len())
+        }
+    }
+
+    /// Like active_matching, but only returns blocks in regions that are awake
+    pub(crate) fn awake_matching<'a>(
+        &'a self,
+        property: Property,
+    ) -> impl Iterator<Item = Target> + 'a {
+        self.activity
+            .awake_blocks()
+            .map(|(x, y)| Block(x, y))
+            .filter(move |&target| self.get(target, property) != 0.0)
+    }
+
+    /// Every block, entity and part of an entity in the world
+    pub(crate) fn all_targets<'a>(&'a self) -> impl Iterator<Item = Target> + 'a {
+        let blocks =
+            (0..GRID_SIZE as i32).flat_map(|x| (0..GRID_SIZE as i32).map(move |y| Block(x, y)));
+        blocks.chain(self.entity_targets())
     }
 }
@@ -0,0 +1,476 @@
+use crate::blocks::*;
+use crate::chemistry::DynamicProperty::*;
+use crate::chemistry::Property::*;
+use crate::chemistry::*;
+use crate::rules::{Phase, UpdateRule, UpdateRules};
+use crate::spellbook::{SpellBinding, SpellSlot, Spellbook};
+use crate::spells::*;
+use bevy::prelude::*;
+use std::fmt;
+
+/// Mana every crafted spell costs, however small it is
+const BASE_COST: f32 = 5.0;
+
+/// Mana each rune adds to the cost of a spell
+const RUNE_COST: f32 = 2.0;
+
+/// Seconds that have to pass between casts of a crafted spell
+const CRAFTED_COOLDOWN: f32 = 0.5;
+
+/// The keys crafted spells are bound to, in the order they're crafted
+const CRAFTED_KEYS: [KeyCode; 6] = [
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+    KeyCode::Key9,
+];
+
+/// The mana of the first crafted spell - the ones before it are left for built-in spells
+const FIRST_CRAFTED_MANA: u8 = 128;
+
+/// A piece of a spell the player can put together with others to craft a spell
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Rune {
+    Adjacent,
+    /// Keeps only the targets the next selector rune doesn't select
+    Not,
+    Is(Property),
+    Add(Property),
+    Send(Property),
+    Receive(Property),
+}
+
+impl fmt::Display for Rune {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rune::Adjacent => write!(f, "Adjacent"),
+            Rune::Not => write!(f, "Not"),
+            Rune::Is(property) => write!(f, "Is {}", property),
+            Rune::Add(property) => write!(f, "Add {}", property),
+            Rune::Send(property) => write!(f, "Send {}", property),
+            Rune::Receive(property) => write!(f, "Receive {}", property),
+        }
+    }
+}
+
+/// Why runes can't be put together into a spell
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum CraftError {
+    /// The spell wouldn't do anything
+    NoEffects,
+    /// Selector runes have to come before every effect rune
+    SelectorAfterEffect,
+    /// A Not rune has to be followed by a selector rune
+    NotWithoutSelector,
+    /// Spells are cast from the player, so materials need an Adjacent rune to reach blocks
+    MaterialWithoutAdjacent,
+    /// Static properties come from the material, so they can't be added, sent or received
+    StaticEffect,
+    /// Every key for crafted spells is taken
+    SpellbookFull,
+}
+
+impl fmt::Display for CraftError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CraftError::NoEffects => write!(f, "The spell needs an effect"),
+            CraftError::SelectorAfterEffect => write!(f, "Effects have to come last"),
+            CraftError::NotWithoutSelector => write!(f, "Not has to come before a selector"),
+            CraftError::MaterialWithoutAdjacent => write!(f, "Materials need Adjacent"),
+            CraftError::StaticEffect => write!(f, "Only materials can give that property"),
+            CraftError::SpellbookFull => write!(f, "The spellbook is full"),
+        }
+    }
+}
+
+/// Put runes together into a spell - selector runes first, each Not applying to the selector
+/// right after it, and then the effects
+pub(crate) fn compile(runes: &[Rune]) -> Result<Spell, CraftError> {
+    let mut selectors = vec![];
+    let mut effects = vec![];
+    let mut negate = false;
+    for rune in runes {
+        let selector = match rune {
+            Rune::Add(_) | Rune::Send(_) | Rune::Receive(_) if negate => {
+                return Err(CraftError::NotWithoutSelector)
+            }
+            Rune::Add(Static(_)) | Rune::Send(Static(_)) | Rune::Receive(Static(_)) => {
+                return Err(CraftError::StaticEffect)
+            }
+            Rune::Add(property) => {
+                effects.push(SpellEffect::Add(*property));
+                continue;
+            }
+            Rune::Send(property) => {
+                effects.push(SpellEffect::Send(*property));
+                continue;
+            }
+            Rune::Receive(property) => {
+                effects.push(SpellEffect::Receive(*property));
+                continue;
+            }
+            _ if !effects.is_empty() => return Err(CraftError::SelectorAfterEffect),
+            Rune::Not if negate => return Err(CraftError::NotWithoutSelector),
+            Rune::Not => {
+                negate = true;
+                continue;
+            }
+            Rune::Adjacent => SpellSelector::Adjacent,
+            Rune::Is(property) => SpellSelector::Is(*property),
+        };
+        selectors.push(if negate { not(selector) } else { selector });
+        negate = false;
+    }
+    if negate {
+        return Err(CraftError::NotWithoutSelector);
+    }
+    if effects.is_empty() {
+        return Err(CraftError::NoEffects);
+    }
+    let gives_material = effects.iter().any(|effect| {
+        matches!(
+            effect,
+            SpellEffect::Add(Material(_)) | SpellEffect::Send(Material(_))
+        )
+    });
+    let reaches_blocks = selectors
+        .iter()
+        .any(|selector| matches!(selector, SpellSelector::Adjacent));
+    if gives_material && !reaches_blocks {
+        return Err(CraftError::MaterialWithoutAdjacent);
+    }
+    Ok(basic(selectors, effects))
+}
+
+/// The mana it takes to cast a spell made of the runes, growing with its size and with how many
+/// targets it reaches
+pub(crate) fn cost(runes: &[Rune]) -> f32 {
+    // Always pay for at least what the spell is expected to spend, so it rarely gets truncated
+    let spent = compile(runes).map_or(0.0, |spell| spell.estimate().cost);
+    BASE_COST + RUNE_COST * runes.len() as f32 + spent
+}
+
+/// The spell the player is crafting, and the runes they can craft it from
+pub(crate) struct Crafting {
+    /// Whether the crafting panel is shown
+    pub(crate) open: bool,
+    /// The runes the player can craft with
+    pub(crate) unlocked: Vec<Rune>,
+    /// The index of the unlocked rune that's selected
+    pub(crate) selected: usize,
+    /// The runes of the spell being crafted, in order
+    pub(crate) runes: Vec<Rune>,
+    /// Why the last spell couldn't be saved, if it couldn't
+    pub(crate) error: Option<CraftError>,
+    /// How many spells have been crafted so far
+    crafted: usize,
+}
+
+impl Default for Crafting {
+    fn default() -> Self {
+        Self {
+            open: false,
+            unlocked: vec![
+                Rune::Adjacent,
+                Rune::Not,
+                Rune::Is(Material(*AIR)),
+                Rune::Is(Material(*WATER)),
+                Rune::Is(Material(*SAND)),
+                Rune::Is(Material(*STONE)),
+                Rune::Is(Dynamic(Burning)),
+                Rune::Add(Material(*WATER)),
+                Rune::Add(Material(*SAND)),
+                Rune::Add(Material(*STONE)),
+                Rune::Add(Dynamic(Burning)),
+                Rune::Add(Dynamic(Charged)),
+                Rune::Send(Material(*AIR)),
+                Rune::Send(Dynamic(Burning)),
+                Rune::Send(Dynamic(Charged)),
+                // Spells are cast from the player, which can't hold a material to receive one
+                Rune::Receive(Dynamic(Burning)),
+            ],
+            selected: 0,
+            runes: vec![],
+            error: None,
+            crafted: 0,
+        }
+    }
+}
+
+impl Crafting {
+    /// Turn the runes into a spell in the spellbook, run by a new player rule
+    pub(crate) fn save(
+        &mut self,
+        spellbook: &mut Spellbook,
+        update_rules: &mut UpdateRules,
+    ) -> Result<(), CraftError> {
+        let spell = compile(&self.runes)?;
+        let key = *CRAFTED_KEYS
+            .get(self.crafted)
+            .ok_or(CraftError::SpellbookFull)?;
+        let mana_id = ManaId(FIRST_CRAFTED_MANA + self.crafted as u8);
+        let name: Vec<String> = self.runes.iter().map(|rune| rune.to_string()).collect();
+        let name = name.join(" ");
+        let color = self
+            .runes
+            .iter()
+            .find_map(|rune| match rune {
+                Rune::Add(Material(id)) | Rune::Send(Material(id)) => {
+                    Some(ALL_BLOCK_DATA[*id as usize].color1)
+                }
+                _ => None,
+            })
+            .unwrap_or(Color::GRAY);
+
+        let rule = SpellRule {
+            name: name.clone().into(),
+            rate: f32::INFINITY,
+            drain: Some(mana_id),
+            phase: Phase::Player,
+            spell,
+        };
+        update_rules.add(UpdateRule::Spell(rule.into()));
+        spellbook.slots.push(SpellSlot::new(
+            &name,
+            mana_id,
+            cost(&self.runes),
+            CRAFTED_COOLDOWN,
+            SpellBinding::Key(key),
+            color,
+        ));
+        self.runes.clear();
+        self.crafted += 1;
+        Ok(())
+    }
+}
+
+/// Lets the player craft spells in a panel opened with the C key
+pub(crate) struct CraftingPlugin;
+
+impl Plugin for CraftingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Crafting>()
+            .add_startup_system(system_setup_crafting_panel)
+            .add_system(system_craft)
+            .add_system(system_update_crafting_panel);
+    }
+}
+
+/// Open and close the panel with C, choose runes with the arrow keys, add them with Enter, take
+/// the last one away with Backspace, and save the spell with Tab
+fn system_craft(
+    keys: Res<Input<KeyCode>>,
+    mut crafting: ResMut<Crafting>,
+    mut spellbook: ResMut<Spellbook>,
+    mut update_rules: ResMut<UpdateRules>,
+) {
+    if keys.just_pressed(KeyCode::C) {
+        crafting.open = !crafting.open;
+    }
+    if !crafting.open {
+        return;
+    }
+
+    let count = crafting.unlocked.len();
+    if keys.just_pressed(KeyCode::Down) {
+        crafting.selected = (crafting.selected + 1) % count;
+    }
+    if keys.just_pressed(KeyCode::Up) {
+        crafting.selected = (crafting.selected + count - 1) % count;
+    }
+    if keys.just_pressed(KeyCode::Return) {
+        let rune = crafting.unlocked[crafting.selected];
+        crafting.runes.push(rune);
+        crafting.error = None;
+    }
+    if keys.just_pressed(KeyCode::Back) {
+        crafting.runes.pop();
+        crafting.error = None;
+    }
+    if keys.just_pressed(KeyCode::Tab) {
+        crafting.error = crafting.save(&mut spellbook, &mut update_rules).err();
+    }
+}
+
+/// The text of the crafting panel
+#[derive(Component)]
+struct CraftingPanel;
+
+fn system_setup_crafting_panel(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    left: Val::Px(8.0),
+                    top: Val::Px(8.0),
+                    ..Default::default()
+                },
+                display: Display::None,
+                ..Default::default()
+            },
+            text: Text::with_section(
+                "",
+                TextStyle {
+                    font: asset_server.load("fonts/DejaVuSansMono.ttf"),
+                    font_size: 14.0,
+                    color: Color::WHITE,
+                },
+                Default::default(),
+            ),
+            ..Default::default()
+        })
+        .insert(CraftingPanel)
+        // So clicks on the panel aren't taken as casts
+        .insert(Interaction::default());
+}
+
+fn system_update_crafting_panel(
+    crafting: Res<Crafting>,
+    mut query: Query<(&mut Style, &mut Text), With<CraftingPanel>>,
+) {
+    if !crafting.is_changed() {
+        return;
+    }
+    for (mut style, mut text) in query.iter_mut() {
+        style.display = if crafting.open {
+            Display::Flex
+        } else {
+            Display::None
+        };
+
+        let mut lines = vec!["Runes (Up/Down to choose, Enter to add):".to_string()];
+        for (i, rune) in crafting.unlocked.iter().enumerate() {
+            let marker = if i == crafting.selected { ">" } else { " " };
+            lines.push(format!("{} {}", marker, rune));
+        }
+        let runes: Vec<String> = crafting.runes.iter().map(|r| r.to_string()).collect();
+        lines.push(String::new());
+        lines.push(format!("Spell (Backspace to undo): {}", runes.join(" ")));
+        lines.push(format!(
+            "Cost: {:.0} mana (Tab to save)",
+            cost(&crafting.runes)
+        ));
+        if let Some(error) = crafting.error {
+            lines.push(error.to_string());
+        }
+        text.sections[0].value = lines.join("\n");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chemistry::StaticProperty::*;
+
+    fn casts(spell: &Spell, info: &WorldInfo, source: Target) -> Vec<Target> {
+        let mut targets = vec![];
+        spell.cast(info, SpellTarget::new(source), &mut |result| {
+            targets.push(result.target.target)
+        });
+        targets
+    }
+
+    #[test]
+    fn runes_compile_into_spells() {
+        let mut info = WorldInfo::default();
+        info.set_block(11, 10, Block::new(*STONE));
+        let spell = compile(&[
+            Rune::Adjacent,
+            Rune::Not,
+            Rune::Is(Material(*AIR)),
+            Rune::Add(Material(*WATER)),
+        ])
+        .unwrap();
+        assert_eq!(
+            casts(&spell, &info, Target::Block(10, 10)),
+            vec![Target::Block(11, 10)]
+        );
+
+        let spell = compile(&[Rune::Send(Dynamic(Burning))]).unwrap();
+        assert_eq!(
+            casts(&spell, &info, Target::Block(10, 10)),
+            vec![Target::Block(10, 10)]
+        );
+    }
+
+    #[test]
+    fn runes_in_the_wrong_order_are_rejected() {
+        let compile_error = |runes: &[Rune]| compile(runes).err();
+        assert_eq!(
+            compile_error(&[Rune::Adjacent]),
+            Some(CraftError::NoEffects)
+        );
+        assert_eq!(
+            compile_error(&[Rune::Add(Material(*WATER)), Rune::Adjacent]),
+            Some(CraftError::SelectorAfterEffect)
+        );
+        assert_eq!(
+            compile_error(&[Rune::Not, Rune::Add(Material(*WATER))]),
+            Some(CraftError::NotWithoutSelector)
+        );
+        assert_eq!(
+            compile_error(&[Rune::Not, Rune::Not, Rune::Is(Static(Liquid))]),
+            Some(CraftError::NotWithoutSelector)
+        );
+        assert_eq!(
+            compile_error(&[Rune::Adjacent, Rune::Add(Static(Light))]),
+            Some(CraftError::StaticEffect)
+        );
+        assert_eq!(
+            compile_error(&[Rune::Is(Material(*AIR)), Rune::Add(Material(*WATER))]),
+            Some(CraftError::MaterialWithoutAdjacent)
+        );
+        assert_eq!(
+            compile_error(&[Rune::Not, Rune::Adjacent, Rune::Send(Material(*AIR))]),
+            Some(CraftError::MaterialWithoutAdjacent)
+        );
+    }
+
+    #[test]
+    fn every_unlocked_effect_rune_compiles() {
+        let crafting = Crafting::default();
+        for rune in &crafting.unlocked {
+            if let Rune::Add(_) | Rune::Send(_) | Rune::Receive(_) = rune {
+                assert!(compile(&[Rune::Adjacent, *rune]).is_ok(), "{}", rune);
+            }
+        }
+        assert!(crafting.unlocked.contains(&Rune::Add(Dynamic(Charged))));
+    }
+
+    #[test]
+    fn spells_reaching_further_cost_more() {
+        let water = Rune::Add(Material(*WATER));
+        let small = cost(&[Rune::Add(Dynamic(Burning))]);
+        let adjacent = cost(&[Rune::Adjacent, water]);
+        let filtered = cost(&[Rune::Adjacent, Rune::Is(Material(*AIR)), water]);
+        let twice = cost(&[Rune::Adjacent, Rune::Adjacent, water]);
+        assert!(small < adjacent);
+        assert!(filtered < adjacent);
+        assert!(adjacent < twice);
+    }
+
+    #[test]
+    fn saved_spells_go_in_the_spellbook() {
+        let mut crafting = Crafting::default();
+        let mut spellbook = Spellbook::default();
+        let mut update_rules = UpdateRules::default();
+        let slots = spellbook.slots.len();
+
+        assert_eq!(
+            crafting.save(&mut spellbook, &mut update_rules),
+            Err(CraftError::NoEffects)
+        );
+        crafting.runes = vec![Rune::Adjacent, Rune::Add(Material(*SAND))];
+        assert_eq!(crafting.save(&mut spellbook, &mut update_rules), Ok(()));
+        assert!(crafting.runes.is_empty());
+
+        let slot = &spellbook.slots[slots];
+        assert_eq!(slot.name, "Adjacent Add Sand");
+        assert_eq!(slot.binding, SpellBinding::Key(KeyCode::Key4));
+        let rule = update_rules.update_rules.last().unwrap();
+        assert_eq!(rule.name(), "Adjacent Add Sand");
+    }
+}
@@ -0,0 +1,253 @@
+use crate::blocks::ALL_BLOCK_DATA;
+use crate::chemistry::DynamicProperty::*;
+use crate::chemistry::Property::*;
+use crate::chemistry::*;
+use bevy::utils::{HashMap, StableHashMap};
+
+/// How well electricity flows through entities
+const ENTITY_CONDUCTANCE: f32 = 0.5;
+
+/// How much electricity leaks out of every conductive target into its surroundings
+const LEAKAGE: f32 = 0.002;
+
+/// The most times per step electricity flows between neighbors - more makes it reach further
+/// each step
+const ITERATIONS: usize = 16;
+
+/// Electricity has settled once no potential changes by more than this in an iteration
+const SETTLED: f32 = 0.0001;
+
+/// Conductive blocks on this row are grounded, standing in for the earth under the world that
+/// drains any electricity reaching it
+const GROUND_ROW: i32 = 0;
+
+/// Potentials below this are treated as no electricity at all
+const MIN_POTENTIAL: f32 = 0.01;
+
+/// The electric potential of every conductive target that has any. It's kept between steps, so
+/// that electricity spreads a bit further through large networks every step.
+#[derive(Default)]
+pub(crate) struct ElectricField {
+    potentials: StableHashMap<Target, f32>,
+}
+
+impl ElectricField {
+    pub(crate) fn get(&self, target: Target) -> f32 {
+        self.potentials.get(&target).cloned().unwrap_or_default()
+    }
+
+    /// Every target with any electricity
+    pub(crate) fn targets(&self) -> impl Iterator<Item = Target> + '_ {
+        self.potentials.keys().cloned()
+    }
+
+    /// The number of targets with any electricity
+    pub(crate) fn len(&self) -> usize {
+        self.potentials.len()
+    }
+}
+
+impl WorldInfo {
+    /// How well electricity flows through a target
+    fn electric_conductance(&self, target: Target) -> f32 {
+        match target {
+            Target::Block(x, y) => self.get_block(x, y).unwrap().data().electric_conductance,
+            Target::Entity(_) | Target::Part(_, _) => ENTITY_CONDUCTANCE,
+        }
+    }
+
+    /// The potential a target is held at, if it is a source or a sink of electricity. Batteries
+    /// and charged targets are sources, and conductive blocks on the `GROUND_ROW` are grounded.
+    fn fixed_potential(&self, target: Target) -> Option<f32> {
+        let charge = self.get(target, Dynamic(Charged));
+        let voltage = match target {
+            Target::Block(x, y) => self.get_block(x, y).unwrap().data().voltage,
+            Target::Entity(_) | Target::Part(_, _) => None,
+        };
+        match (voltage, charge > 0.0, target) {
+            (Some(voltage), _, _) => Some(voltage.max(charge)),
+            (None, true, _) => Some(charge),
+            (None, false, Target::Block(_, y)) if y == GROUND_ROW => Some(0.0),
+            _ => None,
+        }
+    }
+
+    /// Let electricity flow from sources to sinks through the conductive blocks and entities
+    /// connected to them. Networks without a source carry no electricity, so only the ones holding
+    /// a source are worked out, and only until they settle.
+    pub(crate) fn update_electricity(&mut self) {
+        let mut sources: Vec<Target> = ALL_BLOCK_DATA
+            .iter()
+            .enumerate()
+            .filter(|(_, data)| data.voltage.is_some())
+            .flat_map(|(id, _)| self.active_matching(Material(id as u16)))
+            .chain(self.active_matching(Dynamic(Charged)))
+            .filter(|&t| self.electric_conductance(t) > 0.0)
+            .filter(|&t| self.fixed_potential(t).is_some_and(|p| p > 0.0))
+            .collect();
+        // Electricity flows through the nodes in the order they're found, so the sources they're
+        // found from are kept in an order that doesn't depend on how the sets holding them are
+        // hashed
+        sources.sort();
+
+        // Find the networks by spreading out from the sources through everything conductive
+        let mut nodes: Vec<Target> = vec![];
+        let mut index: HashMap<Target, usize> = HashMap::default();
+        for source in sources {
+            index.entry(source).or_insert_with(|| {
+                nodes.push(source);
+                nodes.len() - 1
+            });
+        }
+        let mut neighbors: Vec<Vec<(usize, f32)>> = vec![];
+        while neighbors.len() < nodes.len() {
+            let target = nodes[neighbors.len()];
+            let conductance = self.electric_conductance(target);
+            let mut adjacent = vec![];
+            target.for_each_adjacent(self, |other| {
+                let other_conductance = self.electric_conductance(other);
+                if other_conductance > 0.0 {
+                    let j = *index.entry(other).or_insert_with(|| {
+                        nodes.push(other);
+                        nodes.len() - 1
+                    });
+                    adjacent.push((j, conductance.min(other_conductance)));
+                }
+            });
+            neighbors.push(adjacent);
+        }
+
+        let fixed: Vec<Option<f32>> = nodes.iter().map(|&t| self.fixed_potential(t)).collect();
+        let mut potentials: Vec<f32> = nodes.iter().map(|&t| self.electricity.get(t)).collect();
+        for _ in 0..ITERATIONS {
+            let mut largest_change: f32 = 0.0;
+            for i in 0..nodes.len() {
+                let potential = match fixed[i] {
+                    Some(potential) => potential,
+                    None => {
+                        let (flow, weight) = neighbors[i]
+                            .iter()
+                            .fold((0.0, LEAKAGE), |(flow, weight), &(j, g)| {
+                                (flow + g * potentials[j], weight + g)
+                            });
+                        flow / weight
+                    }
+                };
+                largest_change = largest_change.max((potential - potentials[i]).abs());
+                potentials[i] = potential;
+            }
+            if largest_change < SETTLED {
+                break;
+            }
+        }
+
+        // Everything left out of the networks has lost its source, so loses its electricity
+        self.electricity.potentials = nodes
+            .into_iter()
+            .zip(potentials)
+            .filter(|&(_, potential)| potential >= MIN_POTENTIAL)
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blocks::*;
+    use crate::cells::*;
+    use crate::chemistry::StaticProperty::*;
+    use bevy::math::Vec2;
+    use bevy::prelude::Entity;
+
+    fn electric(info: &WorldInfo, x: i32, y: i32) -> f32 {
+        info.get(Target::Block(x, y), Static(Electric))
+    }
+
+    fn settle(info: &mut WorldInfo) {
+        for _ in 0..20 {
+            info.update_electricity();
+        }
+    }
+
+    #[test]
+    fn batteries_power_wires() {
+        let mut info = WorldInfo::default();
+        set_block_range(&mut info, 10..11, 50..51, *BATTERY);
+        set_block_range(&mut info, 11..30, 50..51, *METAL);
+        set_block_range(&mut info, 40..50, 50..51, *METAL);
+        settle(&mut info);
+
+        assert_eq!(electric(&info, 10, 50), 1.0);
+        assert!(electric(&info, 11, 50) > electric(&info, 29, 50));
+        assert!(electric(&info, 29, 50) > 0.1);
+        // Nothing connects the second wire
+        assert_eq!(electric(&info, 40, 50), 0.0);
+        assert_eq!(electric(&info, 10, 51), 0.0);
+        assert!(info
+            .active_matching(Static(Electric))
+            .any(|t| t == Target::Block(29, 50)));
+    }
+
+    #[test]
+    fn water_conducts_worse_than_metal() {
+        let mut info = WorldInfo::default();
+        set_block_range(&mut info, 10..11, 50..51, *BATTERY);
+        set_block_range(&mut info, 11..20, 50..51, *METAL);
+        set_block_range(&mut info, 10..11, 60..61, *BATTERY);
+        set_block_range(&mut info, 11..20, 60..61, *WATER);
+        settle(&mut info);
+
+        assert!(electric(&info, 19, 60) > 0.0);
+        assert!(electric(&info, 19, 60) < electric(&info, 19, 50));
+    }
+
+    #[test]
+    fn the_ground_drains_electricity() {
+        let mut info = WorldInfo::default();
+        set_block_range(&mut info, 10..11, 10..11, *BATTERY);
+        set_block_range(&mut info, 11..12, 0..11, *METAL);
+        settle(&mut info);
+
+        assert_eq!(electric(&info, 11, 0), 0.0);
+        assert!(electric(&info, 11, 5) < electric(&info, 11, 9));
+    }
+
+    #[test]
+    fn charged_targets_electrify_entities() {
+        let mut info = WorldInfo::default();
+        set_block_range(&mut info, 11..20, 50..51, *METAL);
+        info.set(Target::Block(11, 50), Charged, 1.0);
+        let entity = Entity::from_raw(0);
+        info.entity_colliders.insert(
+            entity,
+            AABBCollider {
+                ll: Vec2::new(18.0, 51.0),
+                ur: Vec2::new(20.0, 53.0),
+            },
+        );
+        settle(&mut info);
+
+        assert!(info.get(Target::Entity(entity), Static(Electric)) > 0.0);
+
+        info.set(Target::Block(11, 50), Charged, 0.0);
+        settle(&mut info);
+        assert_eq!(info.get(Target::Entity(entity), Static(Electric)), 0.0);
+    }
+
+    #[test]
+    fn wires_lose_their_electricity_with_their_battery() {
+        let mut info = WorldInfo::default();
+        set_block_range(&mut info, 10..11, 50..51, *BATTERY);
+        set_block_range(&mut info, 11..20, 50..51, *METAL);
+        // A pool of water with nothing powering it
+        set_block_range(&mut info, 30..60, 50..60, *WATER);
+        settle(&mut info);
+        assert!(electric(&info, 19, 50) > 0.0);
+        assert_eq!(electric(&info, 40, 55), 0.0);
+
+        info.set_block(10, 50, Block::new(*AIR));
+        info.update_electricity();
+        assert_eq!(electric(&info, 11, 50), 0.0);
+        assert_eq!(info.electricity.len(), 0);
+    }
+}
@@ -0,0 +1,383 @@
+//! Small worlds drawn as ASCII art for testing rules, e.g.
+//!
+//! ```text
+//! ..S..
+//! .*C..
+//! #####
+//! ```
+//!
+//! Each character is a block, with the top row drawn first. Materials are drawn with the
+//! characters in `legend`, in upper case, and burning blocks in lower case - or `*` for burning
+//! air. The scene is put in the bottom left corner of the world, so blocks can't fall out of its
+//! bottom or left side, but can leave it through the others.
+
+use crate::blocks::*;
+use crate::cells::set_block_range;
+use crate::chemistry::DynamicProperty::*;
+use crate::chemistry::*;
+use crate::rules::{step, UpdateRule, UpdateRules};
+use crate::spells::NATURAL_RULES;
+use rand::{rngs::StdRng, SeedableRng};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Every scene's randomness is seeded with this, so that it runs the same way every time
+const RANDOM_SEED: u64 = 0;
+
+/// The character each material is drawn with
+fn legend() -> [(char, u16); 15] {
+    [
+        ('.', *AIR),
+        ('#', *STONE),
+        ('W', *WATER),
+        ('S', *SAND),
+        ('L', *WOOD),
+        ('C', *COAL),
+        ('F', *FIRE),
+        ('K', *SMOKE),
+        ('T', *STEAM),
+        ('M', *METAL),
+        ('B', *BATTERY),
+        ('D', *DIRT),
+        ('G', *GRASS),
+        ('V', *VINE),
+        ('O', *SEED),
+    ]
+}
+
+/// The material and whether it's burning for a character of a layout
+pub(crate) fn block_for(c: char) -> Option<(u16, bool)> {
+    if c == '*' {
+        return Some((*AIR, true));
+    }
+    let (_, id) = legend()
+        .into_iter()
+        .find(|&(drawn, _)| drawn == c.to_ascii_uppercase())?;
+    Some((id, c.is_ascii_lowercase()))
+}
+
+/// The character a block is drawn with, or `?` if it can't be drawn
+fn char_for(id: u16, burning: bool) -> char {
+    let drawn = legend()
+        .into_iter()
+        .find(|&(_, material)| material == id)
+        .map_or('?', |(c, _)| c);
+    match (drawn, burning) {
+        (_, false) => drawn,
+        ('.', true) => '*',
+        (c, true) if c.is_ascii_uppercase() => c.to_ascii_lowercase(),
+        _ => '?',
+    }
+}
+
+/// The rows of a layout, ignoring indentation and blank lines
+fn rows(layout: &str) -> Vec<&str> {
+    layout
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect()
+}
+
+pub(crate) struct Scene {
+    pub(crate) info: WorldInfo,
+    width: i32,
+    height: i32,
+}
+
+impl Scene {
+    pub(crate) fn new(layout: &str) -> Scene {
+        let rows = rows(layout);
+        let height = rows.len() as i32;
+        let width = rows.first().map_or(0, |row| row.chars().count()) as i32;
+        let mut info = WorldInfo::default();
+        info.rng = StdRng::seed_from_u64(RANDOM_SEED);
+        for (row, line) in rows.iter().enumerate() {
+            assert_eq!(line.chars().count() as i32, width, "ragged row {:?}", line);
+            for (x, c) in line.chars().enumerate() {
+                let (id, burning) = block_for(c).unwrap_or_else(|| panic!("unknown block {:?}", c));
+                let (x, y) = (x as i32, height - 1 - row as i32);
+                set_block_range(&mut info, x..x + 1, y..y + 1, id);
+                if burning {
+                    info.set(Target::Block(x, y), Burning, 1.0);
+                }
+            }
+        }
+        info.update_light();
+        Scene {
+            info,
+            width,
+            height,
+        }
+    }
+
+    /// Simulate a number of steps, each covering the given number of seconds
+    pub(crate) fn run(&mut self, update_rules: &UpdateRules, steps: usize, timestep: f32) {
+        for _ in 0..steps {
+            step(&mut self.info, update_rules, timestep);
+        }
+    }
+
+    /// The block at a column and row of the layout, counting from the top left
+    pub(crate) fn target(&self, column: i32, row: i32) -> Target {
+        Target::Block(column, self.height - 1 - row)
+    }
+
+    pub(crate) fn get(&self, column: i32, row: i32, property: Property) -> f32 {
+        self.info.get(self.target(column, row), property)
+    }
+
+    /// The scene drawn the same way as the layout it was made from
+    pub(crate) fn layout(&self) -> String {
+        (0..self.height)
+            .map(|row| {
+                (0..self.width)
+                    .map(|column| {
+                        let target = self.target(column, row);
+                        let burning = self.info.get(target, Property::Dynamic(Burning)) > 0.0;
+                        let id = match target {
+                            Target::Block(x, y) => self.info.get_block(x, y).unwrap().id,
+                            _ => unreachable!(),
+                        };
+                        char_for(id, burning)
+                    })
+                    .collect::<String>()
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    pub(crate) fn assert_layout(&self, expected: &str) {
+        let expected = rows(expected).join("\n");
+        let actual = self.layout();
+        assert!(
+            actual == expected,
+            "expected:\n{}\n\nactual:\n{}\n",
+            expected,
+            actual
+        );
+    }
+}
+
+/// The physics rules and every natural rule
+pub(crate) fn all_rules() -> UpdateRules {
+    let update_rules = [UpdateRule::Gravity, UpdateRule::Liquid]
+        .into_iter()
+        .chain(
+            NATURAL_RULES
+                .iter()
+                .map(|rule| UpdateRule::Spell(rule.into())),
+        )
+        .collect();
+    UpdateRules { update_rules }
+}
+
+/// The physics rules and natural rules with the given names
+pub(crate) fn rules(names: &[&str]) -> UpdateRules {
+    let update_rules: Vec<_> = all_rules()
+        .update_rules
+        .into_iter()
+        .filter(|rule| names.contains(&rule.name()))
+        .collect();
+    assert_eq!(
+        update_rules.len(),
+        names.len(),
+        "unknown rules in {:?}",
+        names
+    );
+    UpdateRules { update_rules }
+}
+
+/// A scene saved in `scenarios/`, and what it's run with
+pub(crate) struct Scenario {
+    pub(crate) name: &'static str,
+    pub(crate) rules: fn() -> UpdateRules,
+    pub(crate) steps: usize,
+}
+
+/// The standard scenes, for regression tests and benchmarks
+pub(crate) const SCENARIOS: [Scenario; 4] = [
+    Scenario {
+        name: "sand_avalanche",
+        rules: || rules(&["Gravity", "Liquid"]),
+        steps: 300,
+    },
+    Scenario {
+        name: "water_tank",
+        rules: || rules(&["Gravity", "Liquid"]),
+        steps: 300,
+    },
+    Scenario {
+        name: "coal_fire",
+        rules: all_rules,
+        steps: 300,
+    },
+    Scenario {
+        name: "mixed",
+        rules: all_rules,
+        steps: 300,
+    },
+];
+
+impl Scenario {
+    pub(crate) fn dir(&self) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("scenarios")
+            .join(self.name)
+    }
+
+    /// The scene the scenario starts from
+    pub(crate) fn initial(&self) -> Scene {
+        Scene::new(&read(&self.dir().join("initial.txt")))
+    }
+}
+
+pub(crate) fn read(path: &Path) -> String {
+    fs::read_to_string(path).unwrap_or_else(|e| panic!("couldn't read {:?}: {}", path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layouts_draw_the_scene_they_make() {
+        let layout = "
+            .*C..
+            ..c.W
+            GDSO#
+        ";
+        let scene = Scene::new(layout);
+        scene.assert_layout(layout);
+        assert_eq!(scene.get(2, 1, Property::Dynamic(Burning)), 1.0);
+        assert_eq!(scene.get(4, 2, Property::Material(*STONE)), 1.0);
+    }
+
+    #[test]
+    fn scenes_run_the_same_every_time() {
+        let layout = "
+            #WWSSW.#
+            #SW.WS.#
+            #......#
+            ########
+        ";
+        let update_rules = rules(&["Gravity", "Liquid"]);
+        let mut first = Scene::new(layout);
+        let mut second = Scene::new(layout);
+        first.run(&update_rules, 20, 0.1);
+        second.run(&update_rules, 20, 0.1);
+        assert_eq!(first.layout(), second.layout());
+    }
+
+    #[test]
+    fn sand_falls_and_water_fills_the_bottom() {
+        let mut scene = Scene::new(
+            "
+            #S#WW#
+            #.#WW#
+            #.#..#
+            ######
+        ",
+        );
+        scene.run(&rules(&["Gravity", "Liquid"]), 100, 0.1);
+        scene.assert_layout(
+            "
+            #.#..#
+            #.#WW#
+            #S#WW#
+            ######
+        ",
+        );
+    }
+
+    #[test]
+    fn burning_coal_lights_the_air_and_turns_to_smoke() {
+        let mut scene = Scene::new(
+            "
+            ...
+            .c.
+            ...
+        ",
+        );
+        scene.run(
+            &rules(&["Burning coal lights the air around it on fire"]),
+            1,
+            1.0,
+        );
+        scene.assert_layout(
+            "
+            ***
+            *c*
+            ***
+        ",
+        );
+        scene.run(&rules(&["Burning coal transforms into smoke"]), 100, 1.0);
+        scene.assert_layout(
+            "
+            ***
+            *k*
+            ***
+        ",
+        );
+    }
+
+    #[test]
+    fn fire_lights_coal_and_boils_water() {
+        let mut scene = Scene::new(
+            "
+            C*W
+        ",
+        );
+        scene.run(
+            &rules(&[
+                "Fire makes coal start burning",
+                "Fire turns water into steam",
+            ]),
+            20,
+            1.0,
+        );
+        scene.assert_layout("c*T");
+    }
+
+    #[test]
+    fn fire_spreads_along_plants_and_burns_them_away() {
+        let mut scene = Scene::new(
+            "
+            ..VVg
+            DGGGG
+        ",
+        );
+        let update_rules = rules(&[
+            "Fire spreads to flammable blocks",
+            "Burning grass leaves dirt behind",
+            "Burning vines burn away",
+        ]);
+        scene.run(&update_rules, 50, 0.1);
+        scene.assert_layout(
+            "
+            ..**d
+            Ddddd
+        ",
+        );
+    }
+
+    #[test]
+    fn powered_wires_light_flammable_blocks() {
+        let mut scene = Scene::new(
+            "
+            .L.G
+            BMMM
+            ####
+        ",
+        );
+        let update_rules = rules(&["Electricity sets adjacent flammable blocks on fire"]);
+        scene.run(&update_rules, 20, 0.1);
+        scene.assert_layout(
+            "
+            .l.g
+            BMMM
+            ####
+        ",
+        );
+    }
+}
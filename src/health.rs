@@ -0,0 +1,290 @@
+use crate::blocks::*;
+use crate::chemistry::DynamicProperty::*;
+use crate::chemistry::Property::*;
+use crate::chemistry::StaticProperty::*;
+use crate::chemistry::*;
+use bevy::prelude::*;
+
+/// The health entities start with
+const MAX_HEALTH: f32 = 100.0;
+
+/// How many seconds entities can stay under water before they start drowning
+const MAX_BREATH: f32 = 5.0;
+
+/// Damage per second taken by an entity at an electric potential of 1
+const ELECTROCUTION_DAMAGE: f32 = 40.0;
+
+/// Damage per second taken by an entity that is fully on fire
+const BURNING_DAMAGE: f32 = 20.0;
+
+/// Damage per second taken by an entity under water once it is out of breath
+const DROWNING_DAMAGE: f32 = 15.0;
+
+/// Damage per second taken by an entity for every block falling through it
+const CRUSHING_DAMAGE: f32 = 3.0;
+
+/// How long dead entities stay dead before they respawn, in seconds
+const RESPAWN_DELAY: f32 = 3.0;
+
+#[derive(Component)]
+pub(crate) struct Health {
+    pub(crate) points: f32,
+    /// Seconds left before drowning
+    pub(crate) breath: f32,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self {
+            points: MAX_HEALTH,
+            breath: MAX_BREATH,
+        }
+    }
+}
+
+impl Health {
+    pub(crate) fn damage(&mut self, amount: f32) {
+        self.points = (self.points - amount).max(0.0);
+    }
+
+    pub(crate) fn is_dead(&self) -> bool {
+        self.points <= 0.0
+    }
+
+    /// Take damage from everything around an entity that hurts it, over `dt` seconds, and from the
+    /// falling blocks recorded since it was last updated
+    pub(crate) fn update(&mut self, info: &WorldInfo, entity: Entity, dt: f32) {
+        let target = Target::Entity(entity);
+        let (x, y) = info.entity_block(entity);
+
+        if info.get_block(x, y).map(|block| block.id) == Some(*WATER) {
+            let drowning = (dt - self.breath).max(0.0);
+            self.breath = (self.breath - dt).max(0.0);
+            self.damage(DROWNING_DAMAGE * drowning);
+        } else {
+            self.breath = MAX_BREATH;
+        }
+
+        self.damage(BURNING_DAMAGE * burning(info, entity) * dt);
+        self.damage(ELECTROCUTION_DAMAGE * info.get(target, Static(Electric)) * dt);
+        self.damage(CRUSHING_DAMAGE * info.crushing.get(&entity).cloned().unwrap_or_default());
+    }
+}
+
+/// How much of an entity is on fire - the average over its parts, if it is split into any
+fn burning(info: &WorldInfo, entity: Entity) -> f32 {
+    let (total, count) = info.parts(entity).fold((0.0, 0), |(total, count), part| {
+        (total + info.get(part, Dynamic(Burning)), count + 1)
+    });
+    let whole = info.get(Target::Entity(entity), Dynamic(Burning));
+    if count > 0 {
+        whole.max(total / count as f32)
+    } else {
+        whole
+    }
+}
+
+/// The number of powder blocks inside an entity that moved during the last step
+fn falling_blocks(info: &WorldInfo, entity: Entity) -> usize {
+    let collider = match info.entity_colliders.get(&entity) {
+        Some(collider) => collider,
+        None => return 0,
+    };
+    let (x0, y0) = (collider.ll.x.floor() as i32, collider.ll.y.floor() as i32);
+    let (x1, y1) = (collider.ur.x.ceil() as i32, collider.ur.y.ceil() as i32);
+    (x0..x1)
+        .flat_map(|x| (y0..y1).map(move |y| (x, y)))
+        .filter_map(|(x, y)| info.get_block(x, y))
+        .filter(|block| {
+            block.get(PhysicsFlags::MOVED_THIS_STEP) && block.data().powder_stability > 0.0
+        })
+        .count()
+}
+
+/// Add up the time simulated by a step covering `dt` seconds and the blocks falling through every
+/// entity during it, so that damage is the same however many steps are simulated between frames
+pub(crate) fn record_damage(info: &mut WorldInfo, dt: f32) {
+    info.damage_time += dt;
+    let crushed: Vec<(Entity, usize)> = info
+        .entity_colliders
+        .keys()
+        .map(|&entity| (entity, falling_blocks(info, entity)))
+        .filter(|&(_, count)| count > 0)
+        .collect();
+    for (entity, count) in crushed {
+        *info.crushing.entry(entity).or_default() += count as f32 * dt;
+    }
+}
+
+/// Marks an entity that has run out of health. It can't be controlled until it respawns.
+#[derive(Component)]
+pub(crate) struct Dead {
+    pub(crate) respawn: Timer,
+}
+
+/// The sprites an entity is drawn with while it's alive and once it's dead
+#[derive(Component)]
+pub(crate) struct EntitySprites {
+    pub(crate) alive: Handle<Image>,
+    pub(crate) dead: Handle<Image>,
+}
+
+/// Hurt entities that are burning, drowning, being crushed or electrocuted, over the time
+/// simulated since they were last hurt
+#[allow(clippy::type_complexity)]
+pub(crate) fn damage_system(
+    mut world_query: Query<&mut WorldInfo>,
+    mut query: Query<(Entity, &mut Health), (With<ChemEntity>, Without<Dead>)>,
+) {
+    let mut info = world_query.single_mut();
+    for (entity, mut health) in query.iter_mut() {
+        health.update(&info, entity, info.damage_time);
+    }
+    info.damage_time = 0.0;
+    info.crushing.clear();
+}
+
+/// Kill entities that have run out of health
+pub(crate) fn death_system(
+    mut commands: Commands,
+    mut query: Query<(Entity, &Health, &EntitySprites, &mut Handle<Image>), Without<Dead>>,
+) {
+    for (entity, health, sprites, mut texture) in query.iter_mut() {
+        if health.is_dead() {
+            *texture = sprites.dead.clone();
+            commands.entity(entity).insert(Dead {
+                respawn: Timer::from_seconds(RESPAWN_DELAY, false),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cells::*;
+
+    fn world_with_entity(x: f32, y: f32) -> (WorldInfo, Entity) {
+        let mut info = WorldInfo::default();
+        let entity = Entity::from_raw(0);
+        info.entity_colliders.insert(
+            entity,
+            AABBCollider {
+                ll: Vec2::new(x - 2.0, y - 2.0),
+                ur: Vec2::new(x + 2.0, y + 2.0),
+            },
+        );
+        (info, entity)
+    }
+
+    #[test]
+    fn burning_hurts() {
+        let (mut info, entity) = world_with_entity(50.0, 50.0);
+        let mut health = Health::default();
+        health.update(&info, entity, 1.0);
+        assert_eq!(health.points, MAX_HEALTH);
+
+        info.set(Target::Entity(entity), Burning, 1.0);
+        health.update(&info, entity, 1.0);
+        assert_eq!(health.points, MAX_HEALTH - BURNING_DAMAGE);
+    }
+
+    #[test]
+    fn burning_parts_hurt_in_proportion() {
+        let (mut info, entity) = world_with_entity(50.0, 50.0);
+        info.entity_parts.insert(
+            entity,
+            BodyParts {
+                columns: 2,
+                rows: 2,
+            },
+        );
+        info.set(Target::Part(entity, 0), Burning, 1.0);
+        let mut health = Health::default();
+        health.update(&info, entity, 1.0);
+        assert_eq!(health.points, MAX_HEALTH - BURNING_DAMAGE / 4.0);
+    }
+
+    #[test]
+    fn entities_drown_once_out_of_breath() {
+        let (mut info, entity) = world_with_entity(50.0, 50.0);
+        set_block_range(&mut info, 40..60, 40..60, *WATER);
+        let mut health = Health::default();
+        health.update(&info, entity, MAX_BREATH - 1.0);
+        assert_eq!(health.points, MAX_HEALTH);
+
+        health.update(&info, entity, 2.0);
+        assert_eq!(health.points, MAX_HEALTH - DROWNING_DAMAGE);
+
+        // Surfacing restores breath
+        set_block_range(&mut info, 40..60, 40..60, *AIR);
+        health.update(&info, entity, 1.0);
+        assert_eq!(health.breath, MAX_BREATH);
+    }
+
+    #[test]
+    fn falling_blocks_crush() {
+        let (mut info, entity) = world_with_entity(50.0, 50.0);
+        set_block_range(&mut info, 48..52, 51..52, *SAND);
+        let mut health = Health::default();
+        health.update(&info, entity, 1.0);
+        assert_eq!(health.points, MAX_HEALTH);
+
+        for x in 48..52 {
+            let mut block = info.get_block(x, 51).unwrap();
+            block.set(PhysicsFlags::MOVED_THIS_STEP, true);
+            info.set_block(x, 51, block);
+        }
+        // Two short steps crush as much as one long one
+        record_damage(&mut info, 0.5);
+        record_damage(&mut info, 0.5);
+        health.update(&info, entity, 1.0);
+        assert_eq!(health.points, MAX_HEALTH - 4.0 * CRUSHING_DAMAGE);
+        assert!(!health.is_dead());
+    }
+
+    #[test]
+    fn damage_covers_the_simulated_time() {
+        let mut world = World::new();
+        let entity = world
+            .spawn()
+            .insert(Health::default())
+            .insert(ChemEntity)
+            .id();
+        let mut info = WorldInfo::default();
+        info.entity_colliders.insert(
+            entity,
+            AABBCollider {
+                ll: Vec2::new(48.0, 48.0),
+                ur: Vec2::new(52.0, 52.0),
+            },
+        );
+        info.set(Target::Entity(entity), Burning, 1.0);
+        // A slowed down simulation covers less time than the frames it runs in
+        record_damage(&mut info, 0.25);
+        record_damage(&mut info, 0.25);
+        let world_entity = world.spawn().insert(info).id();
+
+        SystemStage::single(damage_system).run(&mut world);
+        let health = world.get::<Health>(entity).unwrap();
+        assert_eq!(health.points, MAX_HEALTH - BURNING_DAMAGE * 0.5);
+        let info = world.get::<WorldInfo>(world_entity).unwrap();
+        assert_eq!(info.damage_time, 0.0);
+    }
+
+    #[test]
+    fn powered_wires_electrocute() {
+        let (mut info, entity) = world_with_entity(50.0, 50.0);
+        set_block_range(&mut info, 40..41, 40..60, *BATTERY);
+        set_block_range(&mut info, 41..48, 50..51, *METAL);
+        let mut health = Health::default();
+        health.update(&info, entity, 1.0);
+        assert_eq!(health.points, MAX_HEALTH);
+
+        for _ in 0..20 {
+            info.update_electricity();
+        }
+        health.update(&info, entity, 1.0);
+        assert!(health.points < MAX_HEALTH);
+    }
+}
@@ -0,0 +1,89 @@
+mod blocks;
+mod budget;
+mod cells;
+mod chemistry;
+mod crafting;
+mod electricity;
+#[cfg(any(test, feature = "bench"))]
+#[cfg_attr(not(test), allow(dead_code))] // The benchmarks only use part of it
+mod harness;
+mod health;
+mod light;
+mod player;
+mod render;
+mod rules;
+#[cfg(test)]
+mod scenarios;
+mod spatial;
+mod spellbook;
+mod spells;
+mod syntax;
+
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub mod bench;
+
+use bevy::{
+    diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin},
+    prelude::*,
+};
+use bevy_rapier2d::prelude::*;
+use crafting::CraftingPlugin;
+use health::{damage_system, death_system, EntitySprites};
+use player::{
+    cast_spell_system, move_camera_system, move_player_system, respawn_player_system, spawn_player,
+    update_cursor_system, Cursor, MainCamera,
+};
+use render::BlockRenderPlugin;
+use rules::*;
+use spellbook::SpellbookPlugin;
+
+/// Open the game window and run the game until it's closed
+pub fn run() {
+    App::new()
+        .insert_resource(WindowDescriptor {
+            width: 960.0,
+            height: 540.0,
+            vsync: false,
+            ..Default::default()
+        })
+        .add_plugins(DefaultPlugins)
+        .add_plugin(LogDiagnosticsPlugin::default())
+        .add_plugin(FrameTimeDiagnosticsPlugin)
+        .add_plugin(RapierPhysicsPlugin::<NoUserData>::default())
+        .add_plugin(BlockRenderPlugin)
+        .add_plugin(SpellbookPlugin)
+        .add_plugin(CraftingPlugin)
+        .insert_resource(ClearColor(Color::rgb(0.2, 0.2, 0.2)))
+        .insert_resource(RapierConfiguration {
+            gravity: Vector::y() * -1000.0,
+            ..Default::default()
+        })
+        .add_startup_system(setup)
+        .add_startup_system(system_setup_block_grid)
+        .add_system(system_update_block_grid.label("update block grid"))
+        .add_system(move_player_system)
+        .add_system(move_camera_system)
+        .init_resource::<Cursor>()
+        .add_system(update_cursor_system)
+        .add_system(cast_spell_system)
+        .add_system(damage_system.after("update block grid"))
+        .add_system(death_system)
+        .add_system(respawn_player_system)
+        .run();
+}
+
+fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+    asset_server.watch_for_changes().unwrap();
+    spawn_player(
+        &mut commands,
+        EntitySprites {
+            alive: asset_server.load("sprites/cat_alive.png"),
+            dead: asset_server.load("sprites/cat_dead.png"),
+        },
+    );
+
+    let mut camera = OrthographicCameraBundle::new_2d();
+    camera.orthographic_projection.scale = 1.0 / 3.0;
+    commands.spawn_bundle(camera).insert(MainCamera);
+}
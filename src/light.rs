@@ -0,0 +1,293 @@
+use crate::cells::{BlockGrid, CHUNK_SIZE, GRID_SIZE};
+use bevy::utils::{HashMap, HashSet};
+use std::collections::VecDeque;
+
+/// How bright the light coming from the sky is, and the brightest any light can be
+const SKY_LIGHT: f32 = 1.0;
+
+/// How much light is lost for each block it spreads to
+const LIGHT_FALLOFF: f32 = 1.0 / 12.0;
+
+/// How far light can spread from where it's brightest, in blocks
+const LIGHT_RADIUS: i32 = (SKY_LIGHT / LIGHT_FALLOFF) as i32 + 1;
+
+/// The amount of light reaching every block, from 0 to 1. Light comes down from the sky and out of
+/// bright blocks and entities, and spreads through transparent blocks but not opaque ones.
+pub(crate) struct LightField {
+    /// The light of every block
+    levels: Vec<f32>,
+    /// The blocks light came out of last update, and how bright they were
+    sources: HashMap<(i32, i32), f32>,
+    /// Whether the light has been worked out everywhere yet
+    complete: bool,
+}
+
+impl Default for LightField {
+    fn default() -> Self {
+        Self {
+            levels: vec![0.0; GRID_SIZE * GRID_SIZE],
+            sources: Default::default(),
+            complete: false,
+        }
+    }
+}
+
+fn index(x: i32, y: i32) -> usize {
+    y as usize * GRID_SIZE + x as usize
+}
+
+impl LightField {
+    pub(crate) fn get(&self, x: i32, y: i32) -> f32 {
+        if x >= 0 && x < GRID_SIZE as i32 && y >= 0 && y < GRID_SIZE as i32 {
+            self.levels[index(x, y)]
+        } else {
+            SKY_LIGHT
+        }
+    }
+
+    /// Work out the light of every block again from the given light sources, returning the blocks
+    /// whose light changed
+    pub(crate) fn update(
+        &mut self,
+        blocks: &BlockGrid,
+        sources: impl IntoIterator<Item = (i32, i32, f32)>,
+    ) -> Vec<(i32, i32)> {
+        self.sources = collect_sources(sources);
+        self.complete = true;
+        self.relight(blocks, &[GRID_SIZE as i32 - 1; GRID_SIZE])
+    }
+
+    /// Work out the light again only where it could have changed since the last update - around
+    /// the chunks holding changed blocks or light sources that changed, and everywhere below them
+    /// that sunlight could reach through them. Returns the blocks whose light changed.
+    pub(crate) fn update_around(
+        &mut self,
+        blocks: &BlockGrid,
+        sources: impl IntoIterator<Item = (i32, i32, f32)>,
+        changed: impl IntoIterator<Item = (i32, i32)>,
+    ) -> Vec<(i32, i32)> {
+        if !self.complete {
+            return self.update(blocks, sources);
+        }
+        let sources = collect_sources(sources);
+        let moved_sources = sources
+            .iter()
+            .filter(|&(block, light)| self.sources.get(block) != Some(light))
+            .chain(
+                self.sources
+                    .iter()
+                    .filter(|(block, _)| !sources.contains_key(block)),
+            )
+            .map(|(&block, _)| block);
+        let chunks: HashSet<(i32, i32)> = changed
+            .into_iter()
+            .chain(moved_sources)
+            .map(|(x, y)| (x / CHUNK_SIZE as i32, y / CHUNK_SIZE as i32))
+            .collect();
+        self.sources = sources;
+
+        // Light only spreads a short way from a chunk, but sunlight passing through it can reach
+        // all the way down to the bottom of the grid
+        let mut tops = [-1; GRID_SIZE];
+        for (chunk_x, chunk_y) in chunks {
+            let size = CHUNK_SIZE as i32;
+            let x_min = (chunk_x * size - LIGHT_RADIUS).max(0);
+            let x_max = (chunk_x * size + size - 1 + LIGHT_RADIUS).min(GRID_SIZE as i32 - 1);
+            let top = (chunk_y * size + size - 1 + LIGHT_RADIUS).min(GRID_SIZE as i32 - 1);
+            for x in x_min..=x_max {
+                tops[x as usize] = tops[x as usize].max(top);
+            }
+        }
+        self.relight(blocks, &tops)
+    }
+
+    /// Work out the light again in the region made of every column up to its top row, leaving the
+    /// light outside it alone, and return the blocks whose light changed
+    fn relight(&mut self, blocks: &BlockGrid, tops: &[i32; GRID_SIZE]) -> Vec<(i32, i32)> {
+        let in_region =
+            |x: i32, y: i32| x >= 0 && x < GRID_SIZE as i32 && y >= 0 && y <= tops[x as usize];
+        let transparency = |x: i32, y: i32| 1.0 - blocks.get(x, y).unwrap().data().opacity;
+        let region: Vec<(i32, i32)> = (0..GRID_SIZE as i32)
+            .flat_map(|x| (0..=tops[x as usize]).map(move |y| (x, y)))
+            .collect();
+        let old_levels: Vec<f32> = region
+            .iter()
+            .map(|&(x, y)| std::mem::take(&mut self.levels[index(x, y)]))
+            .collect();
+        // Blocks along with the light leaving them, which is less than their own light if they
+        // absorb some of it
+        let mut queue = VecDeque::new();
+
+        // Sunlight shines straight down without losing any light to distance
+        for x in 0..GRID_SIZE as i32 {
+            let top = tops[x as usize];
+            if top < 0 {
+                continue;
+            }
+            let mut light = SKY_LIGHT;
+            for y in (0..GRID_SIZE as i32).rev() {
+                let lit = light;
+                light *= transparency(x, y);
+                if y <= top {
+                    self.levels[index(x, y)] = lit;
+                    queue.push_back((x, y, light));
+                }
+                if light <= 0.0 {
+                    break;
+                }
+            }
+        }
+
+        // Light coming into the region from the blocks around it, which hasn't changed
+        for &(x, y) in &region {
+            for (x2, y2) in [(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)] {
+                if blocks.get(x2, y2).is_some() && !in_region(x2, y2) {
+                    queue.push_back((x2, y2, self.levels[index(x2, y2)] * transparency(x2, y2)));
+                }
+            }
+        }
+
+        for (&(x, y), &light) in &self.sources {
+            if in_region(x, y) && light > self.levels[index(x, y)] {
+                self.levels[index(x, y)] = light;
+            }
+            queue.push_back((x, y, light));
+        }
+
+        while let Some((x, y, light)) = queue.pop_front() {
+            let light = light - LIGHT_FALLOFF;
+            if light <= 0.0 {
+                continue;
+            }
+            for (x2, y2) in [(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)] {
+                if in_region(x2, y2) && light > self.levels[index(x2, y2)] {
+                    self.levels[index(x2, y2)] = light;
+                    queue.push_back((x2, y2, light * transparency(x2, y2)));
+                }
+            }
+        }
+
+        region
+            .into_iter()
+            .zip(old_levels)
+            .filter(|&((x, y), old)| self.levels[index(x, y)] != old)
+            .map(|(block, _)| block)
+            .collect()
+    }
+}
+
+/// The brightest light given off by each block, no brighter than the sky
+fn collect_sources(sources: impl IntoIterator<Item = (i32, i32, f32)>) -> HashMap<(i32, i32), f32> {
+    let mut brightest: HashMap<(i32, i32), f32> = HashMap::default();
+    for (x, y, light) in sources {
+        if x >= 0 && x < GRID_SIZE as i32 && y >= 0 && y < GRID_SIZE as i32 && light > 0.0 {
+            let level = brightest.entry((x, y)).or_default();
+            *level = level.max(light.min(SKY_LIGHT));
+        }
+    }
+    brightest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blocks::*;
+    use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+    fn grid_with(xs: std::ops::Range<i32>, ys: std::ops::Range<i32>, id: u16) -> BlockGrid {
+        let mut blocks = BlockGrid::default();
+        for x in xs {
+            for y in ys.clone() {
+                blocks.set(x, y, Block::new(id));
+            }
+        }
+        blocks
+    }
+
+    #[test]
+    fn sunlight_is_blocked_by_solids() {
+        let top = GRID_SIZE as i32 - 1;
+        let blocks = grid_with(0..GRID_SIZE as i32, 100..110, *STONE);
+        let mut light = LightField::default();
+        light.update(&blocks, []);
+
+        assert_eq!(light.get(5, top), 1.0);
+        assert_eq!(light.get(5, 110), 1.0);
+        // The top of the stone is lit, but nothing gets through it
+        assert_eq!(light.get(5, 109), 1.0);
+        assert_eq!(light.get(5, 108), 0.0);
+        assert_eq!(light.get(5, 50), 0.0);
+    }
+
+    #[test]
+    fn light_fades_with_distance_from_its_source() {
+        // A cave dug out of the stone, with a light in the middle
+        let mut blocks = grid_with(0..GRID_SIZE as i32, 0..200, *STONE);
+        for x in 10..40 {
+            blocks.set(x, 50, Block::new(*AIR));
+        }
+        let mut light = LightField::default();
+        let changed = light.update(&blocks, [(25, 50, 1.0)]);
+        assert!(changed.contains(&(25, 50)));
+
+        assert_eq!(light.get(25, 50), 1.0);
+        assert!(light.get(26, 50) < 1.0);
+        assert!(light.get(30, 50) < light.get(26, 50));
+        assert!(light.get(30, 50) > 0.0);
+        // The walls of the cave are lit, but light doesn't spread through them
+        assert!(light.get(25, 51) > 0.0);
+        assert_eq!(light.get(25, 52), 0.0);
+        assert_eq!(light.get(39 + 12, 50), 0.0);
+
+        // Nothing changes if nothing moved
+        assert!(light.update(&blocks, [(25, 50, 1.0)]).is_empty());
+        assert!(!light.update(&blocks, []).is_empty());
+        assert_eq!(light.get(25, 50), 0.0);
+    }
+
+    #[test]
+    fn updating_around_changes_matches_updating_everything() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let materials = [*AIR, *AIR, *STONE, *WATER, *SMOKE];
+        let mut blocks = BlockGrid::default();
+        for x in 0..GRID_SIZE as i32 {
+            for y in 0..GRID_SIZE as i32 {
+                blocks.set(x, y, Block::new(*materials.choose(&mut rng).unwrap()));
+            }
+        }
+        let random_block = |rng: &mut StdRng| {
+            (
+                rng.gen_range(0..GRID_SIZE as i32),
+                rng.gen_range(0..GRID_SIZE as i32),
+            )
+        };
+        let mut sources: Vec<_> = (0..20)
+            .map(|_| {
+                let (x, y) = random_block(&mut rng);
+                (x, y, rng.gen())
+            })
+            .collect();
+        let mut light = LightField::default();
+        light.update(&blocks, sources.clone());
+        assert!(light.update_around(&blocks, sources.clone(), []).is_empty());
+
+        for _ in 0..10 {
+            let changed: Vec<_> = (0..5).map(|_| random_block(&mut rng)).collect();
+            for &(x, y) in &changed {
+                blocks.set(x, y, Block::new(*materials.choose(&mut rng).unwrap()));
+            }
+            let (x, y) = random_block(&mut rng);
+            sources[0] = (x, y, rng.gen());
+            let before = light.levels.clone();
+            let relit = light.update_around(&blocks, sources.clone(), changed);
+
+            let mut expected = LightField::default();
+            expected.update(&blocks, sources.clone());
+            assert!(light.levels == expected.levels);
+            for (i, level) in light.levels.iter().enumerate() {
+                let block = ((i % GRID_SIZE) as i32, (i / GRID_SIZE) as i32);
+                assert_eq!(*level != before[i], relit.contains(&block));
+            }
+        }
+    }
+}
@@ -1,4 +1,6 @@
-use crate::chemistry::{ChemEntity, DynamicProperty, ManaId, Target, WorldInfo};
+use crate::chemistry::{BodyParts, ChemEntity, DynamicProperty, Property, Target, WorldInfo};
+use crate::health::{Dead, EntitySprites, Health};
+use crate::spellbook::{ManaPool, SpellBinding, Spellbook};
 use bevy::{
     math::{Vec3Swizzles, XY},
     prelude::*,
@@ -8,14 +10,31 @@ use bevy_rapier2d::prelude::*;
 #[derive(Component)]
 pub(crate) struct Player;
 
+/// The camera the world is seen through, which follows the player
+#[derive(Component)]
+pub(crate) struct MainCamera;
+
+/// Where the mouse cursor is in the world, which the player aims their spells at
+#[derive(Default)]
+pub(crate) struct Cursor {
+    pub(crate) position: Option<Vec2>,
+}
+
 const ACCELERATION: Real = 1000.0;
 const DRAG: Real = 10.0;
 const CAMERA_RATE: Real = 4.0;
 
-const SPELL_KEYS: &[(KeyCode, ManaId)] = &[
-    (KeyCode::Key1, ManaId(0)),
-    // TODO: (KeyCode::Key2, ManaId(1))
-];
+/// Where the player comes back to life
+const SPAWN_POINT: Vec2 = Vec2::ZERO;
+
+/// Half the width and height of the player's collider
+const PLAYER_HALF_SIZE: Real = 8.0;
+
+/// The parts the player's body is split into, so that fire spreads across it
+const PLAYER_PARTS: BodyParts = BodyParts {
+    columns: 2,
+    rows: 2,
+};
 
 pub(crate) fn move_player_system(
     input: Res<Input<KeyCode>>,
@@ -24,15 +43,20 @@ pub(crate) fn move_player_system(
             &RigidBodyMassPropsComponent,
             &RigidBodyVelocityComponent,
             &mut RigidBodyForcesComponent,
+            Option<&Dead>,
         ),
         With<Player>,
     >,
 ) {
-    for (mass, velocity, mut forces) in query.iter_mut() {
-        let thrust_unnormalized = vector![
-            thrust_component(&input, KeyCode::D, KeyCode::A),
-            thrust_component(&input, KeyCode::W, KeyCode::S)
-        ];
+    for (mass, velocity, mut forces, dead) in query.iter_mut() {
+        let thrust_unnormalized = if dead.is_some() {
+            Vector::zeros()
+        } else {
+            vector![
+                thrust_component(&input, KeyCode::D, KeyCode::A),
+                thrust_component(&input, KeyCode::W, KeyCode::S)
+            ]
+        };
 
         let thrust = if thrust_unnormalized.norm() < 1e-6 {
             Vector::zeros()
@@ -45,10 +69,11 @@ pub(crate) fn move_player_system(
     }
 }
 
-pub(crate) fn spawn_player(commands: &mut Commands, texture: Handle<Image>) -> Entity {
+pub(crate) fn spawn_player(commands: &mut Commands, sprites: EntitySprites) -> Entity {
     commands
         .spawn_bundle(SpriteBundle {
-            texture,
+            texture: sprites.alive.clone(),
+            transform: Transform::from_translation(SPAWN_POINT.extend(0.0)),
             ..Default::default()
         })
         .insert_bundle(RigidBodyBundle {
@@ -58,18 +83,66 @@ pub(crate) fn spawn_player(commands: &mut Commands, texture: Handle<Image>) -> E
             }),
             ..Default::default()
         })
-        .insert_bundle(ColliderBundle::default())
+        .insert_bundle(ColliderBundle {
+            shape: ColliderShape::cuboid(PLAYER_HALF_SIZE, PLAYER_HALF_SIZE).into(),
+            ..Default::default()
+        })
         .insert(RigidBodyPositionSync::Discrete)
         .insert(Player)
         .insert(ChemEntity)
+        .insert(PLAYER_PARTS)
+        .insert(Health::default())
+        .insert(ManaPool::default())
+        .insert(sprites)
         .id()
 }
 
+/// Bring the player back to life at the spawn point once it has been dead for long enough
+#[allow(clippy::type_complexity)]
+pub(crate) fn respawn_player_system(
+    time: Res<Time>,
+    mut commands: Commands,
+    mut world_query: Query<&mut WorldInfo>,
+    mut query: Query<
+        (
+            Entity,
+            &mut Dead,
+            &mut Health,
+            &EntitySprites,
+            &mut Handle<Image>,
+            &mut RigidBodyPositionComponent,
+            &mut RigidBodyVelocityComponent,
+        ),
+        With<Player>,
+    >,
+) {
+    let mut world = world_query.single_mut();
+    for (entity, mut dead, mut health, sprites, mut texture, mut position, mut velocity) in
+        query.iter_mut()
+    {
+        if !dead.respawn.tick(time.delta()).finished() {
+            continue;
+        }
+        *health = Health::default();
+        *texture = sprites.alive.clone();
+        position.position = SPAWN_POINT.into();
+        velocity.linvel = Vector::zeros();
+        let targets: Vec<Target> = std::iter::once(Target::Entity(entity))
+            .chain(world.parts(entity))
+            .collect();
+        for target in targets {
+            world.set(target, DynamicProperty::Burning, 0.0);
+        }
+        commands.entity(entity).remove::<Dead>();
+    }
+}
+
+#[allow(clippy::type_complexity)]
 pub(crate) fn move_camera_system(
     time: Res<Time>,
     mut query: QuerySet<(
         QueryState<&Transform, With<Player>>,
-        QueryState<&mut Transform, With<Camera>>,
+        QueryState<&mut Transform, With<MainCamera>>,
     )>,
 ) {
     let player_translation = query.q0().single().translation.xy();
@@ -85,17 +158,71 @@ pub(crate) fn move_camera_system(
     camera_transform.translation.y = y;
 }
 
+/// The point in the world a position in a window shows, given the camera it's seen through
+fn window_to_world(
+    position: Vec2,
+    window_size: Vec2,
+    camera: &Camera,
+    camera_transform: &GlobalTransform,
+) -> Vec2 {
+    let ndc = position / window_size * 2.0 - Vec2::ONE;
+    let ndc_to_world = camera_transform.compute_matrix() * camera.projection_matrix.inverse();
+    ndc_to_world.project_point3(ndc.extend(-1.0)).xy()
+}
+
+/// Keep track of where the mouse cursor is in the world
+pub(crate) fn update_cursor_system(
+    windows: Res<Windows>,
+    camera_query: Query<(&Camera, &GlobalTransform), With<MainCamera>>,
+    mut cursor: ResMut<Cursor>,
+) {
+    let window = windows.get_primary();
+    let (camera, camera_transform) = camera_query.single();
+    cursor.position = window.and_then(|window| {
+        let size = Vec2::new(window.width(), window.height());
+        let position = window.cursor_position()?;
+        Some(window_to_world(position, size, camera, camera_transform))
+    });
+}
+
+/// Cast the spells in the spellbook whose bindings were pressed, if the player has the mana,
+/// aiming them at the cursor
+#[allow(clippy::type_complexity)]
 pub(crate) fn cast_spell_system(
-    input: Res<Input<KeyCode>>,
+    keys: Res<Input<KeyCode>>,
+    buttons: Res<Input<MouseButton>>,
+    cursor: Res<Cursor>,
+    mut spellbook: ResMut<Spellbook>,
     mut world_query: Query<&mut WorldInfo>,
-    player_query: Query<Entity, With<Player>>,
+    mut player_query: Query<(Entity, &mut ManaPool), (With<Player>, Without<Dead>)>,
+    interaction_query: Query<&Interaction>,
 ) {
     let mut world = world_query.single_mut();
+    // Clicks on the UI are meant for it, not for spells bound to the mouse
+    let over_ui = interaction_query
+        .iter()
+        .any(|interaction| *interaction != Interaction::None);
 
-    for player in player_query.iter() {
-        for (key, mana_id) in SPELL_KEYS {
-            if input.just_pressed(*key) {
-                world.set(Target::Entity(player), DynamicProperty::Mana(*mana_id), 1.0);
+    for (player, mut mana) in player_query.iter_mut() {
+        match cursor.position {
+            Some(position) => world.aims.insert(player, position),
+            None => world.aims.remove(&player),
+        };
+        let pressed: Vec<usize> = spellbook
+            .just_pressed(&keys, &buttons)
+            .filter(|&slot| {
+                !over_ui || !matches!(spellbook.slots[slot].binding, SpellBinding::Mouse(_))
+            })
+            .collect();
+        for slot in pressed {
+            if let Some(mana_id) = spellbook.try_cast(slot, &mut mana) {
+                // The spell's rule can spend the mana paid for it, along with any paid for casts it
+                // hasn't run yet
+                let slot = &spellbook.slots[slot];
+                info!("Casting {}", slot.name);
+                let target = Target::Entity(player);
+                let paid = world.get(target, Property::Dynamic(DynamicProperty::Mana(mana_id)));
+                world.set(target, DynamicProperty::Mana(mana_id), paid + slot.cost);
             }
         }
     }
@@ -110,3 +237,59 @@ fn thrust_component(input: &Input<KeyCode>, positive: KeyCode, negative: KeyCode
         0.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chemistry::ManaId;
+    use bevy::render::camera::CameraProjection;
+
+    #[test]
+    fn window_positions_map_through_the_camera() {
+        let mut projection = OrthographicProjection {
+            scale: 0.5,
+            ..Default::default()
+        };
+        projection.update(400.0, 200.0);
+        let camera = Camera {
+            projection_matrix: projection.get_projection_matrix(),
+            ..Default::default()
+        };
+        let transform = GlobalTransform::from_xyz(100.0, 50.0, 999.0);
+        let size = Vec2::new(400.0, 200.0);
+
+        let center = window_to_world(Vec2::new(200.0, 100.0), size, &camera, &transform);
+        assert!((center - Vec2::new(100.0, 50.0)).length() < 1e-3);
+        let corner = window_to_world(Vec2::new(400.0, 200.0), size, &camera, &transform);
+        assert!((corner - Vec2::new(200.0, 100.0)).length() < 1e-3);
+    }
+
+    #[test]
+    fn casting_again_adds_to_the_mana_paid() {
+        let mut world = World::new();
+        let player = world
+            .spawn()
+            .insert(Player)
+            .insert(ManaPool::default())
+            .id();
+        let mut info = WorldInfo::default();
+        let mana_id = ManaId(3);
+        info.set(Target::Entity(player), DynamicProperty::Mana(mana_id), 5.0);
+        let world_entity = world.spawn().insert(info).id();
+        let mut keys = Input::<KeyCode>::default();
+        keys.press(KeyCode::Key2);
+        world.insert_resource(keys);
+        world.insert_resource(Input::<MouseButton>::default());
+        world.insert_resource(Cursor::default());
+        world.insert_resource(Spellbook::default());
+
+        SystemStage::single(cast_spell_system).run(&mut world);
+        let cost = world.get_resource::<Spellbook>().unwrap().slots[1].cost;
+        let info = world.get::<WorldInfo>(world_entity).unwrap();
+        let paid = info.get(
+            Target::Entity(player),
+            Property::Dynamic(DynamicProperty::Mana(mana_id)),
+        );
+        assert_eq!(paid, 5.0 + cost);
+    }
+}
@@ -0,0 +1,338 @@
+use crate::blocks::*;
+use bevy::{
+    core::Time,
+    ecs::system::{lifetimeless::SRes, SystemParamItem},
+    prelude::*,
+    reflect::TypeUuid,
+    render::{
+        render_asset::{PrepareAssetError, RenderAsset, RenderAssets},
+        render_resource::{
+            std140::{AsStd140, Std140},
+            *,
+        },
+        renderer::{RenderDevice, RenderQueue},
+        RenderApp, RenderStage,
+    },
+    sprite::{Material2d, Material2dPipeline, Material2dPlugin},
+};
+
+const BLOCK_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 17405104946043011908);
+
+/// The rows of the material table, see `new_material_table`
+const MATERIAL_TABLE_ROWS: u32 = 4;
+
+/// How blocks are drawn, shared by every chunk
+#[derive(Default)]
+pub(crate) struct BlockRenderSettings {
+    /// Draw blocks that have a tile with their tile, shaped by autotile pieces chosen from their
+    /// neighbors, instead of as pixels
+    pub(crate) autotile: bool,
+}
+
+/// Draws the block grid with the block shader
+pub(crate) struct BlockRenderPlugin;
+
+impl Plugin for BlockRenderPlugin {
+    fn build(&self, app: &mut App) {
+        let mut shaders = app.world.get_resource_mut::<Assets<Shader>>().unwrap();
+        shaders.set_untracked(
+            BLOCK_SHADER_HANDLE,
+            Shader::from_wgsl(include_str!("blocks.wgsl")),
+        );
+
+        app.add_plugin(Material2dPlugin::<BlockMaterial>::default())
+            .init_resource::<BlockRenderSettings>()
+            .add_system(system_toggle_autotile);
+        if let Ok(render_app) = app.get_sub_app_mut(RenderApp) {
+            render_app
+                .init_resource::<BlockGlobalsBuffer>()
+                .add_system_to_stage(RenderStage::Extract, extract_block_globals)
+                .add_system_to_stage(RenderStage::Prepare, prepare_block_globals);
+        }
+    }
+}
+
+/// The material of a texture chunk - colors, animations and glow are all worked out by the shader
+/// from the block data in the chunk texture and the material table. Everything that changes every
+/// frame is in `BlockGlobals` instead, so materials only need preparing again when their textures
+/// change.
+#[derive(Debug, Clone, TypeUuid)]
+#[uuid = "05148a04-62c4-484d-8584-0631506135c0"]
+pub(crate) struct BlockMaterial {
+    /// The blocks of the chunk, see `update_texture_pixel`
+    pub(crate) blocks: Handle<Image>,
+    /// The appearance of every block type, see `new_material_table`
+    pub(crate) materials: Handle<Image>,
+    /// The tiles solid blocks are drawn with in autotiled mode
+    pub(crate) tileset: Handle<Image>,
+    /// The edge and corner pieces shaping tiles to their neighbors, see `autotile` in blocks.wgsl
+    pub(crate) pieces: Handle<Image>,
+}
+
+/// Create the table the block shader looks up block types in. Each column is a block type, with
+/// its two colors in the first two rows, its animation and glow in the third, and whether it has a
+/// tile followed by the tile's column and row in the fourth.
+pub(crate) fn new_material_table() -> Image {
+    assert!(
+        ALL_BLOCK_DATA.len() <= 256,
+        "block ids must fit in a byte of the chunk textures"
+    );
+    let colors = |color: fn(&BlockData) -> Color| {
+        ALL_BLOCK_DATA
+            .iter()
+            .map(move |data| color(data).as_linear_rgba_f32())
+    };
+    let effects = ALL_BLOCK_DATA
+        .iter()
+        .map(|data| [data.animation as u32 as f32, data.glow, 0.0, 0.0]);
+    let tiles = ALL_BLOCK_DATA.iter().map(|data| match data.tile {
+        Some((column, row)) => [1.0, column as f32, row as f32, 0.0],
+        None => [0.0; 4],
+    });
+    let data = colors(|data| data.color1)
+        .chain(colors(|data| data.color2))
+        .chain(effects)
+        .chain(tiles)
+        .flatten()
+        .flat_map(f32::to_le_bytes)
+        .collect();
+
+    Image::new(
+        Extent3d {
+            width: ALL_BLOCK_DATA.len() as u32,
+            height: MATERIAL_TABLE_ROWS,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba32Float,
+    )
+}
+
+/// Switch autotiling on and off with the T key
+fn system_toggle_autotile(input: Res<Input<KeyCode>>, mut settings: ResMut<BlockRenderSettings>) {
+    if input.just_pressed(KeyCode::T) {
+        settings.autotile = !settings.autotile;
+    }
+}
+
+/// The uniform data shared by every `BlockMaterial`, must match `BlockGlobals` in blocks.wgsl
+#[derive(Clone, Default, AsStd140)]
+struct BlockGlobals {
+    /// The time driving the animations, in seconds
+    time: f32,
+    /// The block type burning blocks are drawn as
+    burning_material: u32,
+    /// See `BlockRenderSettings`
+    autotile: u32,
+}
+
+/// The buffer holding the `BlockGlobals`, written once a frame and bound by every chunk
+pub(crate) struct BlockGlobalsBuffer {
+    buffer: Buffer,
+}
+
+impl FromWorld for BlockGlobalsBuffer {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.get_resource::<RenderDevice>().unwrap();
+        let buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+            label: Some("block_globals_uniform_buffer"),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            contents: BlockGlobals::default().as_std140().as_bytes(),
+        });
+        BlockGlobalsBuffer { buffer }
+    }
+}
+
+/// Copy the time and render settings into the render world
+fn extract_block_globals(
+    mut commands: Commands,
+    time: Res<Time>,
+    settings: Res<BlockRenderSettings>,
+) {
+    // Wrap around before single precision stops being precise enough for smooth animations
+    let seconds = (time.seconds_since_startup() % 3600.0) as f32;
+    commands.insert_resource(BlockGlobals {
+        time: seconds,
+        burning_material: *FIRE as u32,
+        autotile: settings.autotile as u32,
+    });
+}
+
+fn prepare_block_globals(
+    globals: Res<BlockGlobals>,
+    buffer: Res<BlockGlobalsBuffer>,
+    render_queue: Res<RenderQueue>,
+) {
+    render_queue.write_buffer(&buffer.buffer, 0, globals.as_std140().as_bytes());
+}
+
+pub(crate) struct GpuBlockMaterial {
+    bind_group: BindGroup,
+}
+
+impl RenderAsset for BlockMaterial {
+    type ExtractedAsset = BlockMaterial;
+    type PreparedAsset = GpuBlockMaterial;
+    type Param = (
+        SRes<RenderDevice>,
+        SRes<Material2dPipeline<BlockMaterial>>,
+        SRes<RenderAssets<Image>>,
+        SRes<BlockGlobalsBuffer>,
+    );
+
+    fn extract_asset(&self) -> Self::ExtractedAsset {
+        self.clone()
+    }
+
+    fn prepare_asset(
+        material: Self::ExtractedAsset,
+        (render_device, pipeline, gpu_images, globals): &mut SystemParamItem<Self::Param>,
+    ) -> Result<Self::PreparedAsset, PrepareAssetError<Self::ExtractedAsset>> {
+        let (blocks, materials, tileset, pieces) = match (
+            gpu_images.get(&material.blocks),
+            gpu_images.get(&material.materials),
+            gpu_images.get(&material.tileset),
+            gpu_images.get(&material.pieces),
+        ) {
+            (Some(blocks), Some(materials), Some(tileset), Some(pieces)) => {
+                (blocks, materials, tileset, pieces)
+            }
+            _ => return Err(PrepareAssetError::RetryNextUpdate(material)),
+        };
+
+        let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: globals.buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::TextureView(&blocks.texture_view),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::TextureView(&materials.texture_view),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: BindingResource::TextureView(&tileset.texture_view),
+                },
+                BindGroupEntry {
+                    binding: 4,
+                    resource: BindingResource::TextureView(&pieces.texture_view),
+                },
+            ],
+            label: Some("block_material_bind_group"),
+            layout: &pipeline.material2d_layout,
+        });
+
+        Ok(GpuBlockMaterial { bind_group })
+    }
+}
+
+impl Material2d for BlockMaterial {
+    fn fragment_shader(_asset_server: &AssetServer) -> Option<Handle<Shader>> {
+        Some(BLOCK_SHADER_HANDLE.typed())
+    }
+
+    fn bind_group(render_asset: &<Self as RenderAsset>::PreparedAsset) -> &BindGroup {
+        &render_asset.bind_group
+    }
+
+    fn bind_group_layout(render_device: &RenderDevice) -> BindGroupLayout {
+        // Every texture is read texel by texel, so none of them needs a sampler
+        let texture = |binding, filterable| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Texture {
+                multisampled: false,
+                sample_type: TextureSampleType::Float { filterable },
+                view_dimension: TextureViewDimension::D2,
+            },
+            count: None,
+        };
+        render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: BufferSize::new(
+                            BlockGlobals::std140_size_static() as u64,
+                        ),
+                    },
+                    count: None,
+                },
+                // Chunk texture
+                texture(1, true),
+                // Material table
+                texture(2, false),
+                // Tileset
+                texture(3, true),
+                // Autotile pieces
+                texture(4, true),
+            ],
+            label: Some("block_material_layout"),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cells::TEXTURE_BORDER;
+
+    #[test]
+    fn material_table_has_a_column_per_block_type() {
+        let table = new_material_table();
+        let width = ALL_BLOCK_DATA.len();
+        let texel = |x: usize, y: usize| -> Vec<f32> {
+            let i = 16 * (y * width + x);
+            table.data[i..i + 16]
+                .chunks(4)
+                .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+                .collect()
+        };
+
+        let fire = *FIRE as usize;
+        let fire_data = &ALL_BLOCK_DATA[fire];
+        assert_eq!(texel(fire, 0), fire_data.color1.as_linear_rgba_f32());
+        assert_eq!(texel(fire, 1), fire_data.color2.as_linear_rgba_f32());
+        assert_eq!(texel(fire, 2)[0], BlockAnimation::Flicker as u32 as f32);
+        assert_eq!(texel(fire, 2)[1], 1.0);
+        assert_eq!(texel(*STONE as usize, 2)[1], 0.0);
+        assert_eq!(texel(*STONE as usize, 3), [1.0, 0.0, 1.0, 0.0]);
+        assert_eq!(texel(*AIR as usize, 3), [0.0; 4]);
+    }
+
+    #[test]
+    fn autotile_pieces_are_laid_out_in_a_row() {
+        let pieces = image::open("assets/autotile.png").unwrap().to_rgba8();
+        assert_eq!(pieces.dimensions(), (5 * 8, 8));
+        let alpha = |piece: u32, x: u32, y: u32| pieces.get_pixel(piece * 8 + x, y)[3];
+        // The fill piece is solid, and the outer corners are cut off
+        assert!((0..8).all(|x| (0..8).all(|y| alpha(0, x, y) == 255)));
+        assert_eq!(alpha(1, 0, 0), 0);
+        assert_eq!(alpha(1, 7, 7), 0);
+        assert_eq!(alpha(1, 4, 4), 255);
+    }
+
+    #[test]
+    fn chunk_texture_borders_reach_as_far_as_the_shader_looks() {
+        let shader = include_str!("blocks.wgsl");
+        let constant = |name: &str| -> usize {
+            let prefix = format!("let {}: i32 = ", name);
+            shader
+                .lines()
+                .find_map(|line| line.strip_prefix(&prefix)?.strip_suffix(';')?.parse().ok())
+                .unwrap()
+        };
+        assert_eq!(constant("TEXTURE_BORDER"), TEXTURE_BORDER);
+        assert!(constant("GLOW_RADIUS") <= TEXTURE_BORDER);
+    }
+}
@@ -1,63 +1,187 @@
 use crate::blocks::*;
+use crate::budget::{effect_cost, CAST_COST};
 use crate::cells::*;
 use crate::chemistry::DynamicProperty::*;
 use crate::chemistry::Property::*;
 use crate::chemistry::StaticProperty::*;
 use crate::chemistry::*;
-use crate::spells::SpellSelector::*;
+use crate::health::record_damage;
+use crate::render::*;
 use crate::spells::*;
 use bevy::math::Vec2;
+use bevy::prelude::AssetServer;
 use bevy::prelude::Assets;
 use bevy::prelude::Color;
 use bevy::prelude::Entity;
 use bevy::prelude::Handle;
 use bevy::prelude::Image;
+use bevy::prelude::Time;
 use bevy::prelude::With;
-use bevy::render::render_resource::Extent3d;
-use bevy::render::render_resource::TextureDimension;
-use bevy::render::render_resource::TextureFormat;
 use bevy::sprite::Sprite;
+use bevy::utils::{HashMap, HashSet, StableHashSet};
 use bevy::{
-    math::Vec3,
-    prelude::{info_span, Commands, Query, Res, ResMut, Transform},
-    sprite::SpriteBundle,
+    prelude::{info_span, shape, Commands, Mesh, Query, Res, ResMut, Transform},
+    sprite::{MaterialMesh2dBundle, Mesh2dHandle},
 };
+use bevy_rapier2d::prelude::{
+    ColliderPositionComponent, ColliderShapeComponent, RapierConfiguration,
+};
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::fmt;
+use std::ops::Deref;
+
+/// A group of rules that are run together, in the order the phases are declared
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Phase {
+    /// Movement of powders, liquids, and gasses
+    Physics,
+    /// Fast chemical reactions, like fire spreading
+    Reactions,
+    /// Slow processes, like smoke disappearing
+    Decay,
+    /// Plants growing and dying
+    Growth,
+    /// Spells cast by players
+    Player,
+}
+
+impl Phase {
+    const ALL: [Phase; 5] = [
+        Phase::Physics,
+        Phase::Reactions,
+        Phase::Decay,
+        Phase::Growth,
+        Phase::Player,
+    ];
+
+    /// How many steps pass between each run of this phase
+    fn interval(&self) -> u32 {
+        match self {
+            Phase::Physics => 1,
+            Phase::Reactions => 1,
+            Phase::Decay => 10,
+            Phase::Growth => 10,
+            Phase::Player => 1,
+        }
+    }
+
+    /// Which of the steps in each interval this phase runs on, so that slow phases with the same
+    /// interval don't all run on the same step
+    fn offset(&self) -> u32 {
+        match self {
+            Phase::Growth => 5,
+            _ => 0,
+        }
+    }
+
+    fn runs_on(&self, step: u64) -> bool {
+        step % self.interval() as u64 == self.offset() as u64
+    }
+}
 
 #[derive(Debug)]
 pub(crate) enum UpdateRule {
     Gravity,
     Liquid,
-    Spell(&'static SpellRule),
+    Spell(SpellRuleRef),
+}
+
+/// A spell rule that's either built in, or made while the game runs like crafted spells are
+#[derive(Debug)]
+pub(crate) enum SpellRuleRef {
+    Static(&'static SpellRule),
+    Owned(Box<SpellRule>),
+}
+
+impl Deref for SpellRuleRef {
+    type Target = SpellRule;
+
+    fn deref(&self) -> &SpellRule {
+        match self {
+            SpellRuleRef::Static(rule) => rule,
+            SpellRuleRef::Owned(rule) => rule,
+        }
+    }
+}
+
+impl From<&'static SpellRule> for SpellRuleRef {
+    fn from(rule: &'static SpellRule) -> Self {
+        SpellRuleRef::Static(rule)
+    }
+}
+
+impl From<SpellRule> for SpellRuleRef {
+    fn from(rule: SpellRule) -> Self {
+        SpellRuleRef::Owned(Box::new(rule))
+    }
 }
 
 impl UpdateRule {
-    fn only_run_on(&self) -> Property {
+    /// Find every target this rule could possibly do anything to, using whichever of its
+    /// requirements matches the fewest targets
+    fn candidates(&self, info: &WorldInfo) -> Vec<Target> {
+        let requirements = match self {
+            // Physics only needs to run where something might move
+            UpdateRule::Gravity | UpdateRule::Liquid => {
+                return info.awake_matching(Static(Liquid)).collect()
+            }
+            UpdateRule::Spell(sr) => {
+                let mut requirements = sr.spell.requirements();
+                if let Some(mana_id) = sr.drain {
+                    requirements.push(Requirement::Has(Dynamic(Mana(mana_id))));
+                }
+                requirements
+            }
+        };
+
+        // Each target has at most 8 adjacent blocks, plus a few entities
+        let cost = |requirement: &Requirement| match requirement {
+            Requirement::Has(property) => info.count_matching(*property),
+            Requirement::AdjacentTo(property) => 9 * info.count_matching(*property),
+        };
+        match requirements.into_iter().min_by_key(cost) {
+            Some(Requirement::Has(property)) => info.active_matching(property).collect(),
+            Some(Requirement::AdjacentTo(property)) => {
+                let mut targets = StableHashSet::default();
+                for target in info.active_matching(property) {
+                    target.for_each_adjacent(info, |t| {
+                        targets.insert(t);
+                        // Split entities reach the blocks around them, but blocks only reach
+                        // their parts, so the entity has to be added whenever a part is
+                        if let Target::Part(entity, _) = t {
+                            targets.insert(Target::Entity(entity));
+                        }
+                    });
+                }
+                targets.into_iter().collect()
+            }
+            None => info.all_targets().collect(),
+        }
+    }
+
+    fn phase(&self) -> Phase {
         match self {
-            UpdateRule::Gravity => Static(Liquid),
-            UpdateRule::Liquid => Static(Liquid),
-            UpdateRule::Spell(SpellRule {
-                drain: Some(mana_id),
-                ..
-            }) => Dynamic(Mana(*mana_id)), // TODO: This is hacky.
-            UpdateRule::Spell(sr) => match &sr.spell {
-                Spell::Select(selector, _) => match selector {
-                    Is(property) => *property,
-                    Bind(selector, _) => match **selector {
-                        Is(property) => property,
-                        _ => panic!("Spell started with non-Is selector: {:?}", sr),
-                    },
-                    _ => panic!("Spell started with non-Is selector: {:?}", sr),
-                },
-                _ => panic!("Spell doesn't have any selectors: {:?}", sr),
-            },
+            UpdateRule::Gravity => Phase::Physics,
+            UpdateRule::Liquid => Phase::Physics,
+            UpdateRule::Spell(sr) => sr.phase,
+        }
+    }
+
+    pub(crate) fn name(&self) -> &str {
+        match self {
+            UpdateRule::Gravity => "Gravity",
+            UpdateRule::Liquid => "Liquid",
+            UpdateRule::Spell(sr) => &sr.name,
         }
     }
 
-    fn update(&self, info: &mut WorldInfo, target: Target) {
+    /// Run the rule on a target, covering the given number of seconds
+    fn update(&self, info: &mut WorldInfo, target: Target, dt: f32) {
         match self {
             UpdateRule::Gravity => gravity_update(info, target),
             UpdateRule::Liquid => liquid_update(info, target),
-            UpdateRule::Spell(c) => spell_update(c, info, target),
+            UpdateRule::Spell(c) => spell_update(c, info, target, dt),
         }
     }
 }
@@ -65,7 +189,8 @@ impl UpdateRule {
 fn gravity_update(info: &mut WorldInfo, target: Target) {
     let (x, mut y) = match target {
         Target::Block(x, y) => (x, y),
-        _ => todo!(),
+        // Entities are moved by the physics engine
+        _ => return,
     };
 
     let mut block = info.get_block(x, y).unwrap();
@@ -85,7 +210,14 @@ fn gravity_update(info: &mut WorldInfo, target: Target) {
         let block2_data = block2.data();
 
         let fall_desire = down as f32 * (block2_data.density - block_data.density);
-        if fall_desire <= 0.0 || i as f32 + rand::random::<f32>() > 2.0 * fall_desire {
+        if fall_desire <= 0.0 {
+            break;
+        }
+        if i as f32 + info.rng.gen::<f32>() > 2.0 * fall_desire {
+            // It didn't fall this time, but might next step
+            if i == 0 {
+                info.wake(x, y);
+            }
             break;
         }
 
@@ -105,7 +237,7 @@ fn gravity_update(info: &mut WorldInfo, target: Target) {
 fn liquid_update(info: &mut WorldInfo, target: Target) {
     let (x, y) = match target {
         Target::Block(x, y) => (x, y),
-        _ => todo!(),
+        _ => return,
     };
 
     let mut block = info.get_block(x, y).unwrap();
@@ -118,7 +250,7 @@ fn liquid_update(info: &mut WorldInfo, target: Target) {
         return;
     }
 
-    if rand::random::<f32>() < block_data.powder_stability {
+    if info.rng.gen::<f32>() < block_data.powder_stability {
         block.set(PhysicsFlags::POWDER_STABLE, true);
         info.set_block(x, y, block);
     }
@@ -127,7 +259,7 @@ fn liquid_update(info: &mut WorldInfo, target: Target) {
         return;
     }
 
-    let to_check = neighbors_shuffle(x, y, [-1, 1], [0]);
+    let to_check = neighbors_shuffle(x, y, [-1, 1], [0], &mut info.rng);
     for (x2, y2) in to_check {
         let block2 = info.get_block(x2, y2);
         if block2.is_none() {
@@ -140,10 +272,14 @@ fn liquid_update(info: &mut WorldInfo, target: Target) {
 
         if block2.get(PhysicsFlags::MOVED_THIS_STEP)
             || (density_advantage <= 0.0 && block2_data.physics != BlockPhysics::None)
-            || f32::abs(density_advantage) <= 1.0 * rand::random::<f32>()
         {
             continue;
         }
+        if f32::abs(density_advantage) <= 1.0 * info.rng.gen::<f32>() {
+            // It didn't move this time, but might next step
+            info.wake(x, y);
+            continue;
+        }
 
         block.set(PhysicsFlags::MOVED_THIS_STEP, true);
         if block2_data.physics != BlockPhysics::None {
@@ -174,42 +310,69 @@ fn mark_unstable(info: &mut WorldInfo, x: i32, y: i32, id: u16) {
     }
 }
 
-fn spell_update(spell_rule: &SpellRule, info: &mut WorldInfo, source: Target) {
+/// The chance that an event happening at the given rate per second happens at least once in dt
+/// seconds
+fn chance(rate: f32, dt: f32) -> f32 {
+    1.0 - f32::exp(-rate * dt)
+}
+
+fn spell_update(spell_rule: &SpellRule, info: &mut WorldInfo, source: Target, dt: f32) {
+    // Spells cast with mana can only do as much as the mana given to the caster pays for, and
+    // fizzle if it doesn't even pay for casting them
+    let mut budget = f32::INFINITY;
+    if let Some(mana_id) = spell_rule.drain {
+        let mana = info.get(source, Dynamic(Mana(mana_id)));
+        if mana == 0.0 {
+            return;
+        }
+        budget = mana - CAST_COST;
+        if budget < 0.0 {
+            info.set(source, DynamicProperty::Mana(mana_id), 0.0);
+            return;
+        }
+    }
+
     let mut results = vec![];
     spell_rule
         .spell
         .cast(info, SpellTarget::new(source), &mut |r| results.push(r));
+    // Don't let the order targets are found in decide which ones a truncated spell reaches
+    if budget.is_finite() {
+        results.shuffle(&mut info.rng);
+    }
 
     for result in results {
-        if rand::random::<f32>() > spell_rule.rate {
+        let connection = result.target.connection;
+        // Rules with an infinite rate would apply their effects to anything connected at all, so
+        // they apply them as often as the connection is strong instead
+        let chance = if connection <= 0.0 {
+            0.0
+        } else if spell_rule.rate.is_infinite() {
+            connection.min(1.0)
+        } else {
+            chance(spell_rule.rate * connection, dt)
+        };
+        if info.rng.gen::<f32>() >= chance {
             continue;
         }
+        let cost: f32 = result.effects.iter().map(effect_cost).sum();
+        if cost > budget {
+            break;
+        }
+        budget -= cost;
 
         for effect in result.effects {
-            // TODO: Add/Send should be handled differently.
             match effect {
-                SpellEffect::Summon => todo!(),
-                SpellEffect::Add(Static(_)) => todo!(),
-                SpellEffect::Remove(_) => todo!(),
-                SpellEffect::Add(Material(id)) | SpellEffect::Send(Material(id)) => {
-                    match result.target.target {
-                        Target::Block(x, y) => {
-                            let mut block = info.get_block(x, y).unwrap();
-                            block.id = *id;
-                            info.set_block(x, y, block);
-                        }
-                        Target::Entity(_) => todo!(),
-                    }
+                // Giving mana triggers the rule that drains it, so the mana carries what's left of
+                // this spell's budget along to pay for that rule
+                SpellEffect::Add(Dynamic(Mana(mana_id)))
+                | SpellEffect::Send(Dynamic(Mana(mana_id))) => {
+                    let target = result.target.target;
+                    let held = info.get(target, Dynamic(Mana(*mana_id)));
+                    info.set(target, Mana(*mana_id), held + budget);
+                    budget = 0.0;
                 }
-                SpellEffect::Add(Dynamic(property)) | SpellEffect::Send(Dynamic(property)) => {
-                    info.set(result.target.target, *property, 1.0);
-                }
-                SpellEffect::Send(Static(_)) => todo!(),
-                SpellEffect::Receive(Material(_)) => todo!(),
-                SpellEffect::Receive(Dynamic(property)) => {
-                    info.set(result.target.target, *property, 0.0);
-                }
-                SpellEffect::Receive(Static(_)) => todo!(),
+                _ => apply_effect(info, effect, source, result.target.target),
             }
         }
     }
@@ -219,12 +382,141 @@ fn spell_update(spell_rule: &SpellRule, info: &mut WorldInfo, source: Target) {
     }
 }
 
+fn apply_effect(info: &mut WorldInfo, effect: &SpellEffect, source: Target, target: Target) {
+    match effect {
+        // Spawning entities needs the ECS, which the simulation can't reach, so nothing is summoned
+        SpellEffect::Summon => {}
+        SpellEffect::Add(Material(id)) | SpellEffect::Send(Material(id)) => {
+            set_material(info, target, *id);
+        }
+        SpellEffect::Add(Dynamic(property)) | SpellEffect::Send(Dynamic(property)) => {
+            info.set(target, *property, 1.0);
+        }
+        // Static properties come from the target's material, so they can't be given directly
+        SpellEffect::Add(Static(_)) | SpellEffect::Send(Static(_)) => {}
+        SpellEffect::Remove(Material(id)) => {
+            if info.get(target, Material(*id)) != 0.0 {
+                set_material(info, target, *AIR);
+            }
+        }
+        SpellEffect::Remove(Dynamic(property)) => {
+            info.set(target, *property, 0.0);
+        }
+        // Removing a static property removes the material that provides it
+        SpellEffect::Remove(Static(property)) => {
+            if info.get(target, Static(*property)) != 0.0 {
+                set_material(info, target, *AIR);
+            }
+        }
+        // Pull the target's material into the source, leaving air behind - entities have no
+        // material to hold it, so casting it from them does nothing
+        SpellEffect::Receive(Material(id)) => {
+            if matches!(source, Target::Block(_, _))
+                && source != target
+                && info.get(target, Material(*id)) != 0.0
+            {
+                set_material(info, target, *AIR);
+                set_material(info, source, *id);
+            }
+        }
+        // Move the target's property into the source, which holds no more than 1 of it
+        SpellEffect::Receive(Dynamic(property)) => {
+            let value = info.get(target, Dynamic(*property));
+            if source != target && value != 0.0 {
+                let held = info.get(source, Dynamic(*property));
+                info.set(target, *property, 0.0);
+                info.set(source, *property, (held + value).min(1.0));
+            }
+        }
+        SpellEffect::Receive(Static(_)) => {}
+    }
+}
+
+/// Change the material of a block, keeping its other state - entities have no material
+fn set_material(info: &mut WorldInfo, target: Target, id: u16) {
+    if let Target::Block(x, y) = target {
+        let mut block = info.get_block(x, y).unwrap();
+        block.id = id;
+        info.set_block(x, y, block);
+    }
+}
+
+#[derive(Default)]
 pub(crate) struct UpdateRules {
-    update_rules: Vec<UpdateRule>,
+    pub(crate) update_rules: Vec<UpdateRule>,
+}
+
+impl fmt::Display for UpdateRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UpdateRule::Spell(sr) => write!(f, "{}", **sr),
+            _ => write!(f, "{}", self.name()),
+        }
+    }
+}
+
+impl UpdateRules {
+    pub(crate) fn add(&mut self, rule: UpdateRule) {
+        self.update_rules.push(rule);
+    }
+}
+
+/// The textures the grid is drawn with and the materials drawing them, one for each texture chunk
+pub(crate) struct BlockTextures {
+    chunks: Vec<(Handle<Image>, Handle<BlockMaterial>)>,
+    /// The chunks whose textures changed last frame
+    changed_last_frame: Vec<usize>,
+}
+
+/// The simulated time covered by each step in the game, in seconds
+pub(crate) const TIMESTEP: f32 = 1.0 / 60.0;
+
+/// Runs the simulation in fixed steps, independent of the frame rate
+pub(crate) struct SimulationClock {
+    /// The simulated time covered by each step, in seconds
+    pub(crate) timestep: f32,
+    /// How fast simulated time passes compared to real time
+    pub(crate) speed: f32,
+    /// The most steps to run in one frame - if the simulation falls further behind, it slows down
+    pub(crate) max_steps_per_frame: u32,
+    /// Simulated time that has passed but hasn't been stepped yet
+    accumulator: f32,
+}
+
+impl Default for SimulationClock {
+    fn default() -> Self {
+        Self {
+            timestep: TIMESTEP,
+            speed: 1.0,
+            max_steps_per_frame: 4,
+            accumulator: 0.0,
+        }
+    }
+}
+
+impl SimulationClock {
+    /// Advance the clock by a frame, returning how many steps to run
+    fn advance(&mut self, delta_seconds: f32) -> u32 {
+        self.accumulator += delta_seconds * self.speed;
+        let steps = (self.accumulator / self.timestep) as u32;
+        if steps > self.max_steps_per_frame {
+            self.accumulator = 0.0;
+            self.max_steps_per_frame
+        } else {
+            self.accumulator -= steps as f32 * self.timestep;
+            steps
+        }
+    }
 }
 
 /// Initialize the simulation and its graphics
-pub(crate) fn system_setup_block_grid(mut commands: Commands, mut textures: ResMut<Assets<Image>>) {
+pub(crate) fn system_setup_block_grid(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut textures: ResMut<Assets<Image>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<BlockMaterial>>,
+) {
     let mut info = WorldInfo::default();
     set_block_range(&mut info, 0..GRID_SIZE as i32, 0..GRID_SIZE as i32, *SAND);
     set_block_range(&mut info, 0..GRID_SIZE as i32, 0..GRID_SIZE as i32, *AIR);
@@ -237,104 +529,1013 @@ pub(crate) fn system_setup_block_grid(mut commands: Commands, mut textures: ResM
             info.set(Target::Block(x, y), Burning, 1.0)
         }
     }
+    // A battery wired into the bottom of the water
+    set_block_range(&mut info, 25..26, 5..10, *BATTERY);
+    set_block_range(&mut info, 20..25, 5..6, *METAL);
+    // A patch of dirt with a pond, and seeds falling onto it
+    set_block_range(&mut info, 150..210, 0..10, *DIRT);
+    set_block_range(&mut info, 178..186, 10..14, *WATER);
+    set_block_range(&mut info, 160..166, 30..31, *SEED);
     // set_block_range(&mut info, 135..230, 15..225, *WATER);
+    info.update_light();
 
-    let mut texture = Image::new_fill(
-        Extent3d {
-            width: GRID_SIZE as u32,
-            height: GRID_SIZE as u32,
-            depth_or_array_layers: 1,
-        },
-        TextureDimension::D2,
-        &[0, 0, 0, 0],
-        TextureFormat::Rgba8UnormSrgb,
-    );
-    for x in 0..GRID_SIZE as i32 {
-        for y in 0..GRID_SIZE as i32 {
-            update_texture_pixel(&info, &mut texture, x, y);
-        }
+    let material_table = textures.add(new_material_table());
+    let tileset = asset_server.load("tileset.png");
+    let pieces = asset_server.load("autotile.png");
+    let quad = meshes.add(Mesh::from(shape::Quad::new(Vec2::splat(
+        TEXTURE_CHUNK_SIZE as f32,
+    ))));
+    let mut chunks = vec![];
+    for index in 0..TEXTURE_CHUNKS * TEXTURE_CHUNKS {
+        let texture_handle = textures.add(new_chunk_texture(&info, index));
+        let (x, y) = texture_chunk_origin(index);
+        let center = TEXTURE_CHUNK_SIZE as f32 / 2.0;
+        let material = materials.add(BlockMaterial {
+            blocks: texture_handle.clone(),
+            materials: material_table.clone(),
+            tileset: tileset.clone(),
+            pieces: pieces.clone(),
+        });
+        commands.spawn_bundle(MaterialMesh2dBundle {
+            mesh: Mesh2dHandle(quad.clone()),
+            material: material.clone(),
+            transform: Transform::from_xyz(x as f32 + center, y as f32 + center, 2.0),
+            ..Default::default()
+        });
+        chunks.push((texture_handle, material));
     }
-    let texture_handle = textures.add(texture);
+    commands.insert_resource(BlockTextures {
+        chunks,
+        changed_last_frame: vec![],
+    });
 
     let update_rules: Vec<_> = [UpdateRule::Gravity, UpdateRule::Liquid]
         .into_iter()
-        .chain(NATURAL_RULES.iter().map(UpdateRule::Spell))
-        .chain(PLAYER_RULES.iter().map(UpdateRule::Spell))
+        .chain(
+            NATURAL_RULES
+                .iter()
+                .map(|rule| UpdateRule::Spell(rule.into())),
+        )
+        .chain(
+            PLAYER_RULES
+                .iter()
+                .map(|rule| UpdateRule::Spell(rule.into())),
+        )
         .collect();
 
     commands.insert_resource(UpdateRules { update_rules });
+    commands.insert_resource(SimulationClock::default());
 
-    let scale = 1.0;
-    commands
-        .spawn_bundle(SpriteBundle {
-            transform: Transform::from_xyz(GRID_SIZE as f32 / 2.0, GRID_SIZE as f32 / 2.0, 2.0)
-                .with_scale(Vec3::splat(scale)),
-            texture: texture_handle,
-            ..Default::default()
+    commands.spawn().insert(info);
+}
+
+/// The bounds of an entity in the grid - those of its Rapier collider if it has one, otherwise
+/// those of its sprite
+fn entity_collider(
+    transform: &Transform,
+    sprite: &Sprite,
+    texture: Option<&Image>,
+    shape: Option<(&ColliderShapeComponent, &ColliderPositionComponent)>,
+    physics_scale: f32,
+) -> AABBCollider {
+    if let Some((shape, position)) = shape {
+        let aabb = shape.compute_aabb(position);
+        return AABBCollider {
+            ll: Vec2::new(aabb.mins.x, aabb.mins.y) * physics_scale,
+            ur: Vec2::new(aabb.maxs.x, aabb.maxs.y) * physics_scale,
+        };
+    }
+    let size = sprite
+        .custom_size
+        .or_else(|| {
+            texture.map(|texture| {
+                let size = texture.texture_descriptor.size;
+                Vec2::new(size.width as f32, size.height as f32)
+            })
         })
-        .insert(info);
+        .unwrap_or(Vec2::ONE)
+        * Vec2::new(transform.scale.x, transform.scale.y);
+    let center = Vec2::new(transform.translation.x, transform.translation.y);
+    AABBCollider {
+        ll: center - size / 2.0,
+        ur: center + size / 2.0,
+    }
 }
 
 /// Step the simulation, update the graphics
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
 pub(crate) fn system_update_block_grid(
     // mut block_grid: ResMut<BlockGrid>,
+    time: Res<Time>,
     update_rules: Res<UpdateRules>,
+    mut clock: ResMut<SimulationClock>,
+    mut block_textures: ResMut<BlockTextures>,
+    rapier_config: Res<RapierConfiguration>,
     mut textures: ResMut<Assets<Image>>,
-    mut query: Query<(&mut WorldInfo, &Handle<Image>)>,
-    mut query2: Query<(Entity, &Transform, &mut Sprite), With<ChemEntity>>,
+    mut materials: ResMut<Assets<BlockMaterial>>,
+    mut query: Query<&mut WorldInfo>,
+    mut query2: Query<
+        (
+            Entity,
+            &Transform,
+            &mut Sprite,
+            &Handle<Image>,
+            Option<(&ColliderShapeComponent, &ColliderPositionComponent)>,
+            Option<&BodyParts>,
+        ),
+        With<ChemEntity>,
+    >,
 ) {
-    let (mut info, texture_handle) = query.single_mut();
+    let mut info = query.single_mut();
 
     let span = info_span!("Updating collider bounds").entered();
-    for (entity, transform, _sprite) in query2.iter() {
-        let pos = Vec2::new(transform.translation.x, transform.translation.y);
-        info.entity_colliders.insert(
-            entity,
-            AABBCollider {
-                ll: pos - Vec2::splat(8.0),
-                ur: pos + Vec2::splat(8.0),
-            },
+    let despawned: Vec<Entity> = info
+        .entity_colliders
+        .keys()
+        .filter(|&&entity| query2.get(entity).is_err())
+        .cloned()
+        .collect();
+    for entity in despawned {
+        info.remove_entity(entity);
+    }
+    for (entity, transform, sprite, texture, shape, parts) in query2.iter() {
+        let collider = entity_collider(
+            transform,
+            sprite,
+            textures.get(texture),
+            shape,
+            rapier_config.scale,
         );
+        info.entity_colliders.insert(entity, collider);
+        match parts {
+            Some(&parts) => info.entity_parts.insert(entity, parts),
+            None => info.entity_parts.remove(&entity),
+        };
     }
     span.exit();
 
     let span = info_span!("Stepping blocks").entered();
-    step(&mut info, &update_rules);
+    let mut changed = HashSet::default();
+    for _ in 0..clock.advance(time.delta_seconds()) {
+        step(&mut info, &update_rules, clock.timestep);
+        changed.extend(info.all_changed());
+    }
     span.exit();
 
     let span = info_span!("Updating block sprites").entered();
-    let texture = textures.get_mut(texture_handle).unwrap();
-    for target in info.all_changed() {
-        match target {
-            Target::Block(x, y) => update_texture_pixel(&info, texture, x, y),
-            Target::Entity(_) => {}
+    // Only touch the textures that changed, since each one touched is uploaded again
+    let mut changed_chunks: HashMap<usize, Vec<(i32, i32)>> = HashMap::default();
+    for target in changed {
+        if let Target::Block(x, y) = target {
+            for index in texture_chunks_holding(x, y) {
+                changed_chunks.entry(index).or_default().push((x, y));
+            }
+        }
+    }
+    for (&index, blocks) in &changed_chunks {
+        let texture = textures.get_mut(&block_textures.chunks[index].0).unwrap();
+        for &(x, y) in blocks {
+            update_texture_pixel(&info, texture, index, x, y);
         }
     }
+    // A changed texture is uploaded as a new GPU texture, so the material drawing it has to be
+    // prepared again to bind it. The material can be prepared before the texture is uploaded in the
+    // same frame, so it's prepared again the frame after as well.
+    let changed_now: Vec<usize> = changed_chunks.into_keys().collect();
+    let changed_last_frame = std::mem::replace(&mut block_textures.changed_last_frame, changed_now);
+    for index in changed_last_frame
+        .into_iter()
+        .chain(block_textures.changed_last_frame.iter().cloned())
+    {
+        materials.get_mut(&block_textures.chunks[index].1);
+    }
     span.exit();
 
     let span = info_span!("Updating entity sprites").entered();
-    for (entity, _transform, mut sprite) in query2.iter_mut() {
-        sprite.color = if info.get(Target::Entity(entity), Dynamic(Burning)) > 0.0 {
-            Color::RED
-        } else {
-            Color::WHITE
-        };
+    for (entity, _transform, mut sprite, ..) in query2.iter_mut() {
+        let burning = std::iter::once(Target::Entity(entity))
+            .chain(info.parts(entity))
+            .any(|target| info.get(target, Dynamic(Burning)) > 0.0);
+        sprite.color = if burning { Color::RED } else { Color::WHITE };
         // println!("Color is {:?}", sprite.color);
     }
     span.exit();
 }
 
-fn step(info: &mut WorldInfo, update_rules: &UpdateRules) {
+/// Advance the simulation by a single step covering the given number of seconds
+pub(crate) fn step(info: &mut WorldInfo, update_rules: &UpdateRules, timestep: f32) {
     let span = info_span!("Reset flags").entered();
     info.reset_changes();
+    info.advance_activity();
     span.exit();
 
-    for rule in &update_rules.update_rules {
-        let span = info_span!("Rule", rule = &bevy::utils::tracing::field::debug(rule)).entered();
-        let target_list = info.active_matching(rule.only_run_on()).collect::<Vec<_>>();
-        for target in target_list {
-            rule.update(info, target);
+    for phase in Phase::ALL {
+        let interval = phase.interval();
+        if !phase.runs_on(info.steps) {
+            continue;
+        }
+
+        let span = info_span!(
+            "Phase",
+            phase = &bevy::utils::tracing::field::debug(phase),
+            awake_chunks = info.awake_chunks()
+        )
+        .entered();
+        for rule in update_rules
+            .update_rules
+            .iter()
+            .filter(|r| r.phase() == phase)
+        {
+            let span = info_span!(
+                "Rule",
+                name = rule.name(),
+                rule = &bevy::utils::tracing::field::display(rule)
+            )
+            .entered();
+            // Visit the targets in an order drawn from the world's randomness, since the order
+            // hash sets hold them in changes between versions of Rust
+            let mut target_list = rule.candidates(info);
+            target_list.sort();
+            target_list.shuffle(&mut info.rng);
+            for target in target_list {
+                rule.update(info, target, timestep * interval as f32);
+            }
+            span.exit();
         }
         span.exit();
     }
+
+    let span = info_span!("Updating electricity").entered();
+    info.update_electricity();
+    span.exit();
+
+    let span = info_span!("Recording damage").entered();
+    record_damage(info, timestep);
+    span.exit();
+
+    let span = info_span!("Updating light").entered();
+    info.update_changed_light();
+    span.exit();
+
+    info.steps += 1;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::{rules, Scene};
+    use crate::spells::Spell::*;
+    use crate::spells::SpellEffect::*;
+    use crate::spells::SpellSelector::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn rule(spell: Spell) -> SpellRule {
+        SpellRule {
+            name: "Test rule".into(),
+            rate: f32::INFINITY,
+            drain: None,
+            phase: Phase::Reactions,
+            spell,
+        }
+    }
+
+    /// A rule that applies its effects to every adjacent block made of the given material
+    fn adjacent_rule(id: u16, effects: Vec<SpellEffect>) -> SpellRule {
+        rule(Select(
+            Bind(Box::new(Adjacent), Box::new(Is(Material(id)))),
+            Box::new(Effects(effects)),
+        ))
+    }
+
+    fn material(info: &WorldInfo, x: i32, y: i32) -> u16 {
+        info.get_block(x, y).unwrap().id
+    }
+
+    #[test]
+    fn add_material_changes_block() {
+        let mut info = WorldInfo::default();
+        spell_update(
+            &rule(Effects(vec![Add(Material(*SAND))])),
+            &mut info,
+            Target::Block(1, 1),
+            1.0,
+        );
+        assert_eq!(material(&info, 1, 1), *SAND);
+    }
+
+    #[test]
+    fn add_dynamic_sets_property() {
+        let mut info = WorldInfo::default();
+        let target = Target::Block(1, 1);
+        spell_update(
+            &rule(Effects(vec![Add(Dynamic(Burning))])),
+            &mut info,
+            target,
+            1.0,
+        );
+        assert_eq!(info.get(target, Dynamic(Burning)), 1.0);
+    }
+
+    #[test]
+    fn add_static_does_nothing() {
+        let mut info = WorldInfo::default();
+        set_block_range(&mut info, 1..2, 1..2, *STONE);
+        spell_update(
+            &rule(Effects(vec![Add(Static(Liquid))])),
+            &mut info,
+            Target::Block(1, 1),
+            1.0,
+        );
+        assert_eq!(material(&info, 1, 1), *STONE);
+        assert_eq!(info.get(Target::Block(1, 1), Static(Liquid)), 0.0);
+    }
+
+    #[test]
+    fn send_material_changes_target() {
+        let mut info = WorldInfo::default();
+        set_block_range(&mut info, 2..3, 1..2, *WATER);
+        let spell_rule = adjacent_rule(*WATER, vec![Send(Material(*STEAM))]);
+        spell_update(&spell_rule, &mut info, Target::Block(1, 1), 1.0);
+        assert_eq!(material(&info, 2, 1), *STEAM);
+        assert_eq!(material(&info, 1, 1), *AIR);
+    }
+
+    #[test]
+    fn send_dynamic_sets_target_property() {
+        let mut info = WorldInfo::default();
+        set_block_range(&mut info, 2..3, 1..2, *COAL);
+        let spell_rule = adjacent_rule(*COAL, vec![Send(Dynamic(Burning))]);
+        spell_update(&spell_rule, &mut info, Target::Block(1, 1), 1.0);
+        assert_eq!(info.get(Target::Block(2, 1), Dynamic(Burning)), 1.0);
+        assert_eq!(info.get(Target::Block(1, 1), Dynamic(Burning)), 0.0);
+    }
+
+    #[test]
+    fn send_static_does_nothing() {
+        let mut info = WorldInfo::default();
+        set_block_range(&mut info, 2..3, 1..2, *STONE);
+        let spell_rule = adjacent_rule(*STONE, vec![Send(Static(Liquid))]);
+        spell_update(&spell_rule, &mut info, Target::Block(1, 1), 1.0);
+        assert_eq!(material(&info, 2, 1), *STONE);
+    }
+
+    #[test]
+    fn remove_material_leaves_air() {
+        let mut info = WorldInfo::default();
+        set_block_range(&mut info, 1..3, 1..2, *WOOD);
+        spell_update(
+            &rule(Effects(vec![Remove(Material(*WOOD))])),
+            &mut info,
+            Target::Block(1, 1),
+            1.0,
+        );
+        spell_update(
+            &rule(Effects(vec![Remove(Material(*SAND))])),
+            &mut info,
+            Target::Block(2, 1),
+            1.0,
+        );
+        assert_eq!(material(&info, 1, 1), *AIR);
+        assert_eq!(material(&info, 2, 1), *WOOD);
+    }
+
+    #[test]
+    fn remove_dynamic_clears_property() {
+        let mut info = WorldInfo::default();
+        let target = Target::Block(1, 1);
+        info.set(target, Burning, 1.0);
+        spell_update(
+            &rule(Effects(vec![Remove(Dynamic(Burning))])),
+            &mut info,
+            target,
+            1.0,
+        );
+        assert_eq!(info.get(target, Dynamic(Burning)), 0.0);
+        assert_eq!(info.active_matching(Dynamic(Burning)).count(), 0);
+    }
+
+    #[test]
+    fn remove_static_removes_material() {
+        let mut info = WorldInfo::default();
+        set_block_range(&mut info, 1..2, 1..2, *WATER);
+        set_block_range(&mut info, 2..3, 1..2, *STONE);
+        let spell_rule = rule(Effects(vec![Remove(Static(Liquid))]));
+        spell_update(&spell_rule, &mut info, Target::Block(1, 1), 1.0);
+        spell_update(&spell_rule, &mut info, Target::Block(2, 1), 1.0);
+        assert_eq!(material(&info, 1, 1), *AIR);
+        assert_eq!(material(&info, 2, 1), *STONE);
+    }
+
+    #[test]
+    fn receive_material_pulls_into_source() {
+        let mut info = WorldInfo::default();
+        set_block_range(&mut info, 2..3, 1..2, *WATER);
+        let spell_rule = adjacent_rule(*WATER, vec![Receive(Material(*WATER))]);
+        spell_update(&spell_rule, &mut info, Target::Block(1, 1), 1.0);
+        assert_eq!(material(&info, 1, 1), *WATER);
+        assert_eq!(material(&info, 2, 1), *AIR);
+    }
+
+    #[test]
+    fn receive_material_from_self_does_nothing() {
+        let mut info = WorldInfo::default();
+        set_block_range(&mut info, 1..2, 1..2, *SAND);
+        let spell_rule = rule(Effects(vec![Receive(Material(*SAND))]));
+        spell_update(&spell_rule, &mut info, Target::Block(1, 1), 1.0);
+        assert_eq!(material(&info, 1, 1), *SAND);
+    }
+
+    #[test]
+    fn receive_material_from_entity_does_nothing() {
+        let mut info = WorldInfo::default();
+        set_block_range(&mut info, 2..3, 1..2, *WATER);
+        let entity = Entity::from_raw(0);
+        info.entity_colliders.insert(
+            entity,
+            AABBCollider {
+                ll: Vec2::new(1.0, 1.0),
+                ur: Vec2::new(2.0, 2.0),
+            },
+        );
+        let spell_rule = adjacent_rule(*WATER, vec![Receive(Material(*WATER))]);
+        spell_update(&spell_rule, &mut info, Target::Entity(entity), 1.0);
+        assert_eq!(material(&info, 2, 1), *WATER);
+    }
+
+    #[test]
+    fn receive_dynamic_moves_property_into_source() {
+        let mut info = WorldInfo::default();
+        set_block_range(&mut info, 2..3, 1..2, *COAL);
+        info.set(Target::Block(2, 1), Burning, 0.75);
+        info.set(Target::Block(1, 1), Burning, 0.5);
+        let spell_rule = adjacent_rule(*COAL, vec![Receive(Dynamic(Burning))]);
+        spell_update(&spell_rule, &mut info, Target::Block(1, 1), 1.0);
+        assert_eq!(info.get(Target::Block(2, 1), Dynamic(Burning)), 0.0);
+        assert_eq!(info.get(Target::Block(1, 1), Dynamic(Burning)), 1.0);
+
+        // Receiving from itself leaves the source as it was
+        let target = Target::Block(1, 1);
+        spell_update(
+            &rule(Effects(vec![Receive(Dynamic(Burning))])),
+            &mut info,
+            target,
+            1.0,
+        );
+        assert_eq!(info.get(target, Dynamic(Burning)), 1.0);
+    }
+
+    #[test]
+    fn receive_static_does_nothing() {
+        let mut info = WorldInfo::default();
+        set_block_range(&mut info, 2..3, 1..2, *SAND);
+        let spell_rule = adjacent_rule(*SAND, vec![Receive(Static(Liquid))]);
+        spell_update(&spell_rule, &mut info, Target::Block(1, 1), 1.0);
+        assert_eq!(material(&info, 1, 1), *AIR);
+        assert_eq!(material(&info, 2, 1), *SAND);
+    }
+
+    #[test]
+    fn summon_does_nothing() {
+        let mut info = WorldInfo::default();
+        set_block_range(&mut info, 1..2, 1..2, *STONE);
+        let target = Target::Block(1, 1);
+        spell_update(
+            &rule(Effects(vec![Summon, Add(Dynamic(Burning))])),
+            &mut info,
+            target,
+            1.0,
+        );
+        assert_eq!(material(&info, 1, 1), *STONE);
+        assert_eq!(info.get(target, Dynamic(Burning)), 1.0);
+        assert_eq!(info.entity_colliders.keys().count(), 0);
+    }
+
+    #[test]
+    fn material_effects_on_entities_do_nothing() {
+        let mut info = WorldInfo::default();
+        let target = Target::Entity(Entity::from_raw(0));
+        let spell_rule = rule(Effects(vec![
+            Add(Material(*SAND)),
+            Send(Material(*SAND)),
+            Remove(Material(*SAND)),
+            Receive(Material(*SAND)),
+            Remove(Static(IsEntity)),
+        ]));
+        spell_update(&spell_rule, &mut info, target, 1.0);
+        assert_eq!(info.get(target, Material(*SAND)), 0.0);
+        assert_eq!(info.get(target, Static(IsEntity)), 1.0);
+    }
+
+    #[test]
+    fn connection_scales_effect_chance() {
+        let spell_rule = SpellRule {
+            rate: 1.0,
+            ..rule(Select(
+                Is(Dynamic(Burning)),
+                Box::new(Effects(vec![Add(Material(*SMOKE))])),
+            ))
+        };
+        let mut info = WorldInfo::default();
+        info.rng = StdRng::seed_from_u64(0);
+        for x in 0..GRID_SIZE as i32 {
+            info.set(Target::Block(x, 0), Burning, 0.25);
+            info.set(Target::Block(x, 1), Burning, 1.0);
+            spell_update(&spell_rule, &mut info, Target::Block(x, 0), 1.0);
+            spell_update(&spell_rule, &mut info, Target::Block(x, 1), 1.0);
+        }
+        let smoke = |y| {
+            (0..GRID_SIZE as i32)
+                .filter(|&x| material(&info, x, y) == *SMOKE)
+                .count()
+        };
+        // Expected counts are 57 and 162
+        assert!((30..90).contains(&smoke(0)));
+        assert!((130..195).contains(&smoke(1)));
+    }
+
+    #[test]
+    fn connection_is_the_chance_for_infinite_rates() {
+        let spell_rule = rule(Select(
+            Area(5.0),
+            Box::new(Effects(vec![Add(Material(*SAND))])),
+        ));
+        let mut info = WorldInfo::default();
+        info.rng = StdRng::seed_from_u64(0);
+        let (mut near, mut far) = (0, 0);
+        for _ in 0..100 {
+            set_block_range(&mut info, 45..56, 45..56, *AIR);
+            spell_update(&spell_rule, &mut info, Target::Block(50, 50), 1.0);
+            near += (material(&info, 51, 50) == *SAND) as i32;
+            far += (material(&info, 55, 50) == *SAND) as i32;
+        }
+
+        // The block next to the caster is connected five times as strongly as the one at the edge
+        assert!((70..95).contains(&near));
+        assert!((5..30).contains(&far));
+    }
+
+    #[test]
+    fn phases_run_on_their_interval() {
+        let spell_rule = SpellRule {
+            phase: Phase::Decay,
+            ..rule(Select(
+                Is(Material(*SAND)),
+                Box::new(Effects(vec![Add(Material(*STONE))])),
+            ))
+        };
+        let update_rules = UpdateRules {
+            update_rules: vec![UpdateRule::Spell(spell_rule.into())],
+        };
+        let mut info = WorldInfo::default();
+        set_block_range(&mut info, 1..2, 1..2, *SAND);
+
+        info.steps = 1;
+        for _ in 1..Phase::Decay.interval() {
+            step(&mut info, &update_rules, 1.0);
+            assert_eq!(material(&info, 1, 1), *SAND);
+        }
+        step(&mut info, &update_rules, 1.0);
+        assert_eq!(material(&info, 1, 1), *STONE);
+    }
+
+    #[test]
+    fn slow_phases_run_on_different_steps() {
+        for step in 0..100 {
+            assert!(!(Phase::Decay.runs_on(step) && Phase::Growth.runs_on(step)));
+        }
+        assert_eq!((0..100).filter(|&s| Phase::Growth.runs_on(s)).count(), 10);
+    }
+
+    #[test]
+    fn chance_converts_rates_per_second() {
+        assert_eq!(chance(0.0, 1.0), 0.0);
+        assert_eq!(chance(f32::INFINITY, 1.0 / 60.0), 1.0);
+        assert!((chance(2.0, 0.5) - 0.632).abs() < 0.001);
+        assert!((chance(1.0, 0.1) - chance(0.1, 1.0)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn clock_runs_fixed_steps() {
+        let mut clock = SimulationClock {
+            timestep: 0.25,
+            ..Default::default()
+        };
+        assert_eq!(clock.advance(0.6), 2);
+        assert_eq!(clock.advance(0.2), 1);
+        assert_eq!(clock.advance(0.1), 0);
+        assert_eq!(clock.advance(10.0), clock.max_steps_per_frame);
+        assert_eq!(clock.advance(0.0), 0);
+
+        clock.speed = 0.5;
+        assert_eq!(clock.advance(0.5), 1);
+    }
+
+    fn candidates(spell_rule: SpellRule, info: &WorldInfo) -> Vec<Target> {
+        let mut targets = UpdateRule::Spell(spell_rule.into()).candidates(info);
+        targets.sort_by_key(|t| match t {
+            Target::Block(x, y) => (*x, *y),
+            Target::Entity(_) | Target::Part(_, _) => (-1, -1),
+        });
+        targets
+    }
+
+    fn select(selectors: Vec<SpellSelector>) -> Spell {
+        let selector = selectors
+            .into_iter()
+            .rev()
+            .reduce(|acc, s| Bind(Box::new(s), Box::new(acc)))
+            .unwrap();
+        Select(selector, Box::new(Effects(vec![])))
+    }
+
+    #[test]
+    fn candidates_use_most_selective_property() {
+        let mut info = WorldInfo::default();
+        set_block_range(&mut info, 0..10, 0..10, *COAL);
+        info.set(Target::Block(3, 4), Burning, 1.0);
+        let spell = select(vec![Is(Material(*COAL)), Is(Dynamic(Burning))]);
+        assert_eq!(candidates(rule(spell), &info), vec![Target::Block(3, 4)]);
+    }
+
+    #[test]
+    fn candidates_for_rules_starting_with_adjacent() {
+        let mut info = WorldInfo::default();
+        set_block_range(&mut info, 5..6, 5..6, *WATER);
+        let spell = select(vec![Adjacent, Is(Material(*WATER))]);
+        let targets = candidates(rule(spell), &info);
+        assert_eq!(targets.len(), 8);
+        assert!(targets.contains(&Target::Block(4, 6)));
+        assert!(!targets.contains(&Target::Block(5, 5)));
+    }
+
+    #[test]
+    fn candidates_for_rules_starting_with_not() {
+        let mut info = WorldInfo::default();
+        set_block_range(&mut info, 5..6, 5..6, *WATER);
+        let spell = select(vec![Not(Box::new(Is(Material(*WATER))))]);
+        assert_eq!(candidates(rule(spell), &info).len(), GRID_SIZE * GRID_SIZE);
+        assert_eq!(
+            candidates(rule(Effects(vec![])), &info).len(),
+            GRID_SIZE * GRID_SIZE
+        );
+    }
+
+    #[test]
+    fn candidates_include_entities() {
+        let mut info = WorldInfo::default();
+        let entity = Entity::from_raw(0);
+        info.entity_colliders.insert(
+            entity,
+            AABBCollider {
+                ll: Vec2::new(5.5, 5.5),
+                ur: Vec2::new(6.5, 6.5),
+            },
+        );
+        info.set(Target::Block(0, 0), Burning, 1.0);
+        info.set(Target::Block(1, 0), Burning, 1.0);
+        let spell = select(vec![Is(Dynamic(Burning)), Is(Static(IsEntity))]);
+        assert_eq!(candidates(rule(spell), &info), vec![Target::Entity(entity)]);
+
+        let spell = select(vec![Adjacent, Is(Static(IsEntity))]);
+        assert_eq!(candidates(rule(spell), &info).len(), 4);
+    }
+
+    #[test]
+    fn candidates_include_entities_split_into_parts() {
+        let mut info = WorldInfo::default();
+        let entity = Entity::from_raw(0);
+        info.entity_colliders.insert(
+            entity,
+            AABBCollider {
+                ll: Vec2::new(5.5, 5.5),
+                ur: Vec2::new(7.5, 7.5),
+            },
+        );
+        info.entity_parts.insert(
+            entity,
+            BodyParts {
+                columns: 2,
+                rows: 2,
+            },
+        );
+        info.set(Target::Block(5, 5), Burning, 1.0);
+        // Spells cast by the whole entity can reach the burning block, so it has to be a candidate
+        let spell = select(vec![Adjacent, Is(Dynamic(Burning))]);
+        let targets = candidates(rule(spell), &info);
+        assert!(targets.contains(&Target::Entity(entity)));
+        assert!(targets.contains(&Target::Part(entity, 0)));
+        assert!(!targets.contains(&Target::Part(entity, 3)));
+    }
+
+    #[test]
+    fn drain_rules_need_mana() {
+        let spell_rule = SpellRule {
+            drain: Some(ManaId(0)),
+            ..rule(Effects(vec![Add(Material(*SAND))]))
+        };
+        let mut info = WorldInfo::default();
+        spell_update(&spell_rule, &mut info, Target::Block(1, 1), 1.0);
+        assert_eq!(material(&info, 1, 1), *AIR);
+
+        info.set(Target::Block(1, 1), Mana(ManaId(0)), 1.0);
+        assert_eq!(candidates(spell_rule, &info), vec![Target::Block(1, 1)]);
+    }
+
+    #[test]
+    fn spells_only_do_what_their_mana_pays_for() {
+        let spell_rule = SpellRule {
+            drain: Some(ManaId(0)),
+            ..adjacent_rule(*AIR, vec![Add(Material(*WATER))])
+        };
+        let source = Target::Block(5, 5);
+        let watered = |info: &WorldInfo| {
+            (4..7)
+                .flat_map(|x| (4..7).map(move |y| (x, y)))
+                .filter(|&(x, y)| material(info, x, y) == *WATER)
+                .count()
+        };
+
+        let mut info = WorldInfo::default();
+        info.set(source, Mana(ManaId(0)), CAST_COST + 3.0);
+        spell_update(&spell_rule, &mut info, source, 1.0);
+        assert_eq!(watered(&info), 3);
+        assert_eq!(info.get(source, Dynamic(Mana(ManaId(0)))), 0.0);
+
+        let mut info = WorldInfo::default();
+        info.set(source, Mana(ManaId(0)), 100.0);
+        spell_update(&spell_rule, &mut info, source, 1.0);
+        assert_eq!(watered(&info), 8);
+    }
+
+    #[test]
+    fn spells_fizzle_without_the_mana_to_cast_them() {
+        let spell_rule = SpellRule {
+            drain: Some(ManaId(0)),
+            ..rule(Effects(vec![Add(Material(*SAND))]))
+        };
+        let source = Target::Block(1, 1);
+        let mut info = WorldInfo::default();
+        info.set(source, Mana(ManaId(0)), CAST_COST / 2.0);
+        spell_update(&spell_rule, &mut info, source, 1.0);
+        assert_eq!(material(&info, 1, 1), *AIR);
+        assert_eq!(info.get(source, Dynamic(Mana(ManaId(0)))), 0.0);
+    }
+
+    #[test]
+    fn launched_fireballs_set_fire() {
+        let update_rules = UpdateRules {
+            update_rules: PLAYER_RULES
+                .iter()
+                .filter(|r| r.name == "Launch fireball" || r.name == "Fireball")
+                .map(|rule| UpdateRule::Spell(rule.into()))
+                .collect(),
+        };
+        let mut info = WorldInfo::default();
+        info.rng = StdRng::seed_from_u64(0);
+        set_block_range(&mut info, 0..20, 0..5, *STONE);
+        let source = Target::Block(10, 5);
+        info.set(source, Mana(ManaId(1)), 30.0);
+        step(&mut info, &update_rules, TIMESTEP);
+
+        // The mana left after launching the fireball pays for the fire it places
+        let fire = (0..20)
+            .flat_map(|x| (0..12).map(move |y| (x, y)))
+            .filter(|&(x, y)| material(&info, x, y) == *FIRE)
+            .count();
+        assert!(fire > 0);
+        assert_eq!(info.get(source, Dynamic(Mana(ManaId(2)))), 0.0);
+    }
+
+    #[test]
+    fn settled_blocks_fall_asleep() {
+        let mut info = WorldInfo::default();
+        set_block_range(&mut info, 9..12, 0..10, *STONE);
+        set_block_range(&mut info, 9..10, 10..40, *STONE);
+        set_block_range(&mut info, 11..12, 10..40, *STONE);
+        set_block_range(&mut info, 10..11, 20..30, *SAND);
+        let update_rules = rules(&["Gravity", "Liquid"]);
+        for _ in 0..200 {
+            step(&mut info, &update_rules, 1.0);
+        }
+        for y in 10..20 {
+            assert_eq!(material(&info, 10, y), *SAND);
+        }
+        step(&mut info, &update_rules, 1.0);
+        assert_eq!(info.awake_chunks(), 0);
+    }
+
+    #[test]
+    fn set_block_wakes_neighbors() {
+        let mut info = WorldInfo::default();
+        let update_rules = rules(&["Gravity", "Liquid"]);
+        step(&mut info, &update_rules, 1.0);
+        assert_eq!(info.awake_chunks(), 0);
+
+        // A block on a chunk corner wakes all four chunks around it
+        set_block_range(&mut info, 16..17, 16..17, *SAND);
+        step(&mut info, &update_rules, 1.0);
+        assert_eq!(info.awake_chunks(), 4);
+        assert_eq!(material(&info, 16, 16), *AIR);
+    }
+
+    #[test]
+    fn burning_lights_up_caves() {
+        let mut info = WorldInfo::default();
+        set_block_range(&mut info, 0..GRID_SIZE as i32, 100..110, *STONE);
+        let update_rules = rules(&[
+            "Burning things give off light",
+            "Things stop giving off light once they stop burning",
+        ]);
+        step(&mut info, &update_rules, 1.0);
+        assert_eq!(info.get(Target::Block(20, 50), Static(Light)), 0.0);
+        assert_eq!(info.get(Target::Block(20, 150), Static(Light)), 1.0);
+
+        info.set(Target::Block(20, 50), Burning, 1.0);
+        step(&mut info, &update_rules, 1.0);
+        assert_eq!(info.get(Target::Block(20, 50), Dynamic(Bright)), 1.0);
+        assert_eq!(info.get(Target::Block(20, 50), Static(Light)), 1.0);
+        assert!(info.get(Target::Block(23, 50), Static(Light)) > 0.0);
+        assert!(info.all_changed().any(|t| t == Target::Block(23, 50)));
+        assert!(info
+            .active_matching(Static(Light))
+            .any(|t| t == Target::Block(23, 50)));
+
+        info.set(Target::Block(20, 50), Burning, 0.0);
+        step(&mut info, &update_rules, 1.0);
+        assert_eq!(info.get(Target::Block(23, 50), Static(Light)), 0.0);
+    }
+
+    #[test]
+    fn fire_spreads_across_split_entities_from_where_it_touches() {
+        let mut info = WorldInfo::default();
+        let entity = Entity::from_raw(0);
+        info.entity_colliders.insert(
+            entity,
+            AABBCollider {
+                ll: Vec2::new(50.0, 50.0),
+                ur: Vec2::new(54.0, 54.0),
+            },
+        );
+        info.entity_parts.insert(
+            entity,
+            BodyParts {
+                columns: 2,
+                rows: 2,
+            },
+        );
+        info.set(Target::Block(49, 50), Burning, 1.0);
+        let update_rules = rules(&["Burning materials light adjacent entities on fire"]);
+        let burning = |info: &WorldInfo, target| info.get(target, Dynamic(Burning)) > 0.0;
+
+        let mut steps = 0;
+        while !burning(&info, Target::Part(entity, 0)) {
+            step(&mut info, &update_rules, 0.1);
+            steps += 1;
+            assert!(steps < 1000);
+        }
+        // Only the part touching the fire catches at first
+        assert!(!burning(&info, Target::Part(entity, 3)));
+        assert!(!burning(&info, Target::Entity(entity)));
+
+        for _ in 0..200 {
+            step(&mut info, &update_rules, 0.1);
+        }
+        assert!(burning(&info, Target::Part(entity, 3)));
+        assert!(burning(&info, Target::Entity(entity)));
+    }
+
+    #[test]
+    fn grass_grows_on_lit_dirt_near_water() {
+        let mut scene = Scene::new(
+            "
+            ..........W..
+            DDDDDDDDDDDD#
+            DDDDDDDDDDDD#
+            DDDDDDDDDDDD#
+        ",
+        );
+        scene.run(&rules(&["Grass grows on lit dirt near water"]), 300, 1.0);
+        // Too dark below the surface, and too far from water on the left
+        scene.assert_layout(
+            "
+            ..........W..
+            DDDDDDDGGGGG#
+            DDDDDDDDDDDD#
+            DDDDDDDDDDDD#
+        ",
+        );
+    }
+
+    #[test]
+    fn grass_dies_in_the_dark() {
+        let mut scene = Scene::new(
+            "
+            .G...
+            #####
+            #...#
+            #.G.#
+            #####
+        ",
+        );
+        scene.run(
+            &rules(&["Grass turns back into dirt in the dark"]),
+            100,
+            1.0,
+        );
+        scene.assert_layout(
+            "
+            .G...
+            #####
+            #...#
+            #.D.#
+            #####
+        ",
+        );
+    }
+
+    #[test]
+    fn vines_hang_down_from_grass() {
+        let mut scene = Scene::new(
+            "
+            .G.
+            ...
+            ...
+            ...
+        ",
+        );
+        let update_rules = rules(&[
+            "Vines hang down from grass",
+            "Vines grow down into the light",
+        ]);
+        scene.run(&update_rules, 400, 2.0);
+        scene.assert_layout(
+            "
+            .G.
+            .V.
+            .V.
+            .V.
+        ",
+        );
+    }
+
+    #[test]
+    fn seeds_sprout_on_dirt_near_water() {
+        let mut scene = Scene::new(
+            "
+            .........O..
+            ............
+            O.......O.W.
+            DDDDDDDDDDDD
+        ",
+        );
+        scene.run(&rules(&["Seeds sprout on dirt near water"]), 100, 1.0);
+        // Too far from water, and not on dirt
+        scene.assert_layout(
+            "
+            .........O..
+            ............
+            O.......G.W.
+            DDDDDDDDDDDD
+        ",
+        );
+    }
+
+    #[test]
+    fn plants_burn() {
+        let mut scene = Scene::new(
+            "
+            vVVVVVVVVV
+            ..........
+            gGGGGGGGGG
+        ",
+        );
+        let update_rules = rules(&[
+            "Fire spreads to flammable blocks",
+            "Burning grass leaves dirt behind",
+            "Burning vines burn away",
+        ]);
+        scene.run(&update_rules, 100, 1.0);
+        scene.assert_layout(
+            "
+            **********
+            ..........
+            dddddddddd
+        ",
+        );
+    }
+
+    #[test]
+    fn fire_spreads_into_wood() {
+        let mut scene = Scene::new(
+            "
+            lLLLLLLLLL
+        ",
+        );
+        scene.run(&rules(&["Fire spreads to flammable blocks"]), 100, 1.0);
+        scene.assert_layout(
+            "
+            llllllllll
+        ",
+        );
+    }
 }
@@ -0,0 +1,151 @@
+//! Regression tests that run saved scenes for a number of steps, and check they end up the same
+//! as when they were saved. Each scenario is a directory in `scenarios/` holding `initial.txt` and
+//! `expected.txt`, drawn the way `harness` draws scenes.
+//!
+//! When a scenario ends up differently, an image of the expected scene, the actual one, and the
+//! blocks that differ between them is saved to `scenario-diffs` in the target directory - `target`,
+//! or `CARGO_TARGET_DIR` if it's set. Running the tests with `UPDATE_SCENARIOS=1` saves the actual
+//! scenes as the expected ones instead.
+
+use crate::blocks::*;
+use crate::harness::*;
+use crate::rules::TIMESTEP;
+use bevy::prelude::Color;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The width and height of each block in diff images, in pixels
+const CELL_SIZE: u32 = 8;
+
+/// What blocks that differ are drawn with in diff images
+const DIFFERENT: Color = Color::rgb(1.0, 0.0, 1.0);
+
+/// What empty space is drawn with in diff images
+const BACKGROUND: Color = Color::rgb(0.1, 0.1, 0.15);
+
+/// The color a character of a layout is drawn with in diff images
+fn color(c: char) -> Color {
+    let (id, burning) = block_for(c).unwrap_or((*AIR, false));
+    let color = ALL_BLOCK_DATA[id as usize].color1;
+    let color = if burning && id == *AIR {
+        ALL_BLOCK_DATA[*FIRE as usize].color1
+    } else {
+        color
+    };
+    // Blend see-through blocks like air and water over the background
+    let [r, g, b, a] = color.as_rgba_f32();
+    let [br, bg, bb, _] = BACKGROUND.as_rgba_f32();
+    Color::rgb(
+        r * a + br * (1.0 - a),
+        g * a + bg * (1.0 - a),
+        b * a + bb * (1.0 - a),
+    )
+}
+
+/// Draw the expected layout, the actual one, and the blocks that differ between them side by side
+fn save_diff(name: &str, expected: &str, actual: &str) -> PathBuf {
+    let expected: Vec<Vec<char>> = expected.lines().map(|l| l.chars().collect()).collect();
+    let actual: Vec<Vec<char>> = actual.lines().map(|l| l.chars().collect()).collect();
+    let rows = expected.len().max(actual.len()) as u32;
+    let columns = expected
+        .iter()
+        .chain(actual.iter())
+        .map(|row| row.len())
+        .max()
+        .unwrap_or(0) as u32;
+    let panel_width = (columns + 1) * CELL_SIZE;
+    let mut image = image::RgbImage::new(3 * panel_width, rows * CELL_SIZE);
+
+    let cell = |layout: &Vec<Vec<char>>, row: u32, column: u32| {
+        layout
+            .get(row as usize)
+            .and_then(|row| row.get(column as usize))
+            .cloned()
+    };
+    for row in 0..rows {
+        for column in 0..columns {
+            let expected = cell(&expected, row, column);
+            let actual = cell(&actual, row, column);
+            let colors = [
+                expected.map_or(BACKGROUND, color),
+                actual.map_or(BACKGROUND, color),
+                if expected == actual {
+                    // Keep the scene faintly visible behind the differences
+                    let [r, g, b, _] = actual.map_or(BACKGROUND, color).as_rgba_f32();
+                    Color::rgb(r * 0.3, g * 0.3, b * 0.3)
+                } else {
+                    DIFFERENT
+                },
+            ];
+            for (panel, color) in colors.into_iter().enumerate() {
+                let [r, g, b, _] = color.as_rgba_f32();
+                let pixel = image::Rgb([(r * 255.0) as u8, (g * 255.0) as u8, (b * 255.0) as u8]);
+                for dx in 0..CELL_SIZE {
+                    for dy in 0..CELL_SIZE {
+                        let x = panel as u32 * panel_width + column * CELL_SIZE + dx;
+                        image.put_pixel(x, row * CELL_SIZE + dy, pixel);
+                    }
+                }
+            }
+        }
+    }
+
+    let target =
+        std::env::var_os("CARGO_TARGET_DIR").map_or_else(|| "target".into(), PathBuf::from);
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join(target)
+        .join("scenario-diffs");
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(format!("{}.png", name));
+    image.save(&path).unwrap();
+    path
+}
+
+/// Run a scenario, returning why it failed if it didn't end up as expected
+fn run(scenario: &Scenario) -> Result<(), String> {
+    let mut scene = scenario.initial();
+    scene.run(&(scenario.rules)(), scenario.steps, TIMESTEP);
+    let actual = scene.layout();
+
+    let expected_path = scenario.dir().join("expected.txt");
+    if std::env::var_os("UPDATE_SCENARIOS").is_some() {
+        fs::write(&expected_path, actual + "\n").unwrap();
+        return Ok(());
+    }
+    let expected = Scene::new(&read(&expected_path)).layout();
+    if expected == actual {
+        return Ok(());
+    }
+    let path = save_diff(scenario.name, &expected, &actual);
+    Err(format!(
+        "{} ended up differently, see {:?}\nexpected:\n{}\n\nactual:\n{}\n",
+        scenario.name, path, expected, actual
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scenarios_end_up_as_saved() {
+        let failures: Vec<String> = SCENARIOS.iter().filter_map(|s| run(s).err()).collect();
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+
+    #[test]
+    fn diffs_mark_the_blocks_that_differ() {
+        let path = save_diff("diff_test", "S.\n.W", "S.\nW.");
+        let image = image::open(&path).unwrap().to_rgb8();
+        let panel_width = 3 * CELL_SIZE;
+        let diff_pixel = |column: u32, row: u32| {
+            *image.get_pixel(2 * panel_width + column * CELL_SIZE, row * CELL_SIZE)
+        };
+        let different = image::Rgb([255, 0, 255]);
+        assert_ne!(diff_pixel(0, 0), different);
+        assert_ne!(diff_pixel(1, 0), different);
+        assert_eq!(diff_pixel(0, 1), different);
+        assert_eq!(diff_pixel(1, 1), different);
+        fs::remove_file(path).unwrap();
+    }
+}
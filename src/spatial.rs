@@ -0,0 +1,172 @@
+use crate::chemistry::AABBCollider;
+use bevy::{
+    math::Vec2,
+    prelude::Entity,
+    utils::{StableHashMap, StableHashSet},
+};
+
+/// The width and height of the cells colliders are bucketed into, in blocks
+const CELL_SIZE: f32 = 32.0;
+
+/// The colliders of all entities, bucketed by the cells of a coarse grid they overlap, so that
+/// finding the entities in a region doesn't need to look at every entity in the world. Entities
+/// are hashed the same way every run, so they're found in a repeatable order.
+#[derive(Default)]
+pub(crate) struct EntityColliders {
+    colliders: StableHashMap<Entity, AABBCollider>,
+    cells: StableHashMap<(i32, i32), StableHashSet<Entity>>,
+}
+
+/// The first and last cells a collider overlaps
+fn cell_range(collider: &AABBCollider) -> ((i32, i32), (i32, i32)) {
+    let cell = |point: Vec2| {
+        let cell = (point / CELL_SIZE).floor();
+        (cell.x as i32, cell.y as i32)
+    };
+    (cell(collider.ll), cell(collider.ur))
+}
+
+fn cells_in((ll, ur): ((i32, i32), (i32, i32))) -> impl Iterator<Item = (i32, i32)> {
+    (ll.0..=ur.0).flat_map(move |x| (ll.1..=ur.1).map(move |y| (x, y)))
+}
+
+impl EntityColliders {
+    pub(crate) fn get(&self, entity: &Entity) -> Option<&AABBCollider> {
+        self.colliders.get(entity)
+    }
+
+    pub(crate) fn keys(&self) -> impl Iterator<Item = &Entity> + '_ {
+        self.colliders.keys()
+    }
+
+    pub(crate) fn insert(&mut self, entity: Entity, collider: AABBCollider) {
+        let new_cells = cell_range(&collider);
+        match self.colliders.insert(entity, collider) {
+            Some(old) if cell_range(&old) == new_cells => return,
+            Some(old) => self.remove_from_cells(entity, &old),
+            None => {}
+        }
+        for cell in cells_in(new_cells) {
+            self.cells.entry(cell).or_default().insert(entity);
+        }
+    }
+
+    pub(crate) fn remove(&mut self, entity: &Entity) -> Option<AABBCollider> {
+        let collider = self.colliders.remove(entity)?;
+        self.remove_from_cells(*entity, &collider);
+        Some(collider)
+    }
+
+    fn remove_from_cells(&mut self, entity: Entity, collider: &AABBCollider) {
+        for cell in cells_in(cell_range(collider)) {
+            if let Some(entities) = self.cells.get_mut(&cell) {
+                entities.remove(&entity);
+                if entities.is_empty() {
+                    self.cells.remove(&cell);
+                }
+            }
+        }
+    }
+
+    /// Every entity whose collider intersects the region
+    pub(crate) fn intersecting(
+        &self,
+        region: AABBCollider,
+    ) -> impl Iterator<Item = (&Entity, &AABBCollider)> + '_ {
+        let (region_ll, region_ur) = cell_range(&region);
+        cells_in((region_ll, region_ur))
+            .filter_map(move |cell| Some((cell, self.cells.get(&cell)?)))
+            .flat_map(move |(cell, entities)| {
+                entities.iter().filter_map(move |entity| {
+                    let (entity, collider) = self.colliders.get_key_value(entity).unwrap();
+                    // Entities spanning several cells are only returned from the first cell they
+                    // share with the region
+                    let (ll, _) = cell_range(collider);
+                    let first = (ll.0.max(region_ll.0), ll.1.max(region_ll.1));
+                    if cell == first && collider.intersects(&region) {
+                        Some((entity, collider))
+                    } else {
+                        None
+                    }
+                })
+            })
+    }
+
+    /// Every entity whose collider is within a distance of the region
+    pub(crate) fn within(
+        &self,
+        region: AABBCollider,
+        distance: f32,
+    ) -> impl Iterator<Item = (&Entity, &AABBCollider)> + '_ {
+        let expanded = AABBCollider {
+            ll: region.ll - Vec2::splat(distance),
+            ur: region.ur + Vec2::splat(distance),
+        };
+        self.intersecting(expanded)
+            .filter(move |(_, collider)| collider.distance(&region) <= distance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collider(x: f32, y: f32, size: f32) -> AABBCollider {
+        AABBCollider {
+            ll: Vec2::new(x, y),
+            ur: Vec2::new(x + size, y + size),
+        }
+    }
+
+    fn found(colliders: &EntityColliders, region: AABBCollider) -> Vec<u32> {
+        let mut ids: Vec<u32> = colliders
+            .intersecting(region)
+            .map(|(entity, _)| entity.id())
+            .collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    #[test]
+    fn finds_each_intersecting_entity_once() {
+        let mut colliders = EntityColliders::default();
+        colliders.insert(Entity::from_raw(0), collider(10.0, 10.0, 4.0));
+        // Spans four cells
+        colliders.insert(Entity::from_raw(1), collider(30.0, 30.0, 4.0));
+        colliders.insert(Entity::from_raw(2), collider(100.0, 100.0, 4.0));
+
+        assert_eq!(found(&colliders, collider(0.0, 0.0, 64.0)), vec![0, 1]);
+        assert_eq!(found(&colliders, collider(33.0, 33.0, 1.0)), vec![1]);
+        assert_eq!(
+            found(&colliders, collider(20.0, 20.0, 5.0)),
+            Vec::<u32>::new()
+        );
+        let near: Vec<_> = colliders
+            .within(collider(20.0, 20.0, 5.0), 8.0)
+            .map(|(entity, _)| entity.id())
+            .collect();
+        assert_eq!(near, vec![1]);
+    }
+
+    #[test]
+    fn moved_and_removed_entities_leave_their_old_cells() {
+        let mut colliders = EntityColliders::default();
+        let entity = Entity::from_raw(0);
+        colliders.insert(entity, collider(10.0, 10.0, 4.0));
+        colliders.insert(entity, collider(200.0, 10.0, 4.0));
+        assert_eq!(
+            found(&colliders, collider(0.0, 0.0, 32.0)),
+            Vec::<u32>::new()
+        );
+        assert_eq!(found(&colliders, collider(190.0, 0.0, 32.0)), vec![0]);
+
+        colliders.remove(&entity);
+        assert_eq!(
+            found(&colliders, collider(190.0, 0.0, 32.0)),
+            Vec::<u32>::new()
+        );
+        assert!(colliders.cells.is_empty());
+        assert_eq!(colliders.keys().count(), 0);
+        assert!(colliders.remove(&entity).is_none());
+    }
+}
@@ -0,0 +1,369 @@
+use crate::chemistry::ManaId;
+use crate::player::Player;
+use bevy::prelude::*;
+
+/// The mana the player starts with, and can regenerate up to
+const MAX_MANA: f32 = 100.0;
+
+/// Mana regenerated per second
+const MANA_REGEN: f32 = 10.0;
+
+/// The size of each slot in the HUD, in pixels
+const SLOT_SIZE: f32 = 32.0;
+
+/// The size of the mana bar in the HUD, in pixels
+const MANA_BAR_SIZE: Size<f32> = Size {
+    width: 200.0,
+    height: 12.0,
+};
+
+/// What the player presses to cast a spell
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum SpellBinding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+}
+
+impl SpellBinding {
+    fn just_pressed(&self, keys: &Input<KeyCode>, buttons: &Input<MouseButton>) -> bool {
+        match self {
+            SpellBinding::Key(key) => keys.just_pressed(*key),
+            SpellBinding::Mouse(button) => buttons.just_pressed(*button),
+        }
+    }
+}
+
+/// A spell the player can cast, and what casting it takes
+#[derive(Clone, Debug)]
+pub(crate) struct SpellSlot {
+    pub(crate) name: String,
+    /// The mana the spell's rule drains from the caster - casting gives it to them
+    pub(crate) mana_id: ManaId,
+    /// The mana taken from the player's pool by each cast
+    pub(crate) cost: f32,
+    /// Seconds that have to pass between casts
+    pub(crate) cooldown: f32,
+    pub(crate) binding: SpellBinding,
+    /// The color of the slot in the HUD
+    pub(crate) color: Color,
+    /// Seconds until the spell can be cast again
+    pub(crate) cooldown_left: f32,
+}
+
+impl SpellSlot {
+    pub(crate) fn new(
+        name: &str,
+        mana_id: ManaId,
+        cost: f32,
+        cooldown: f32,
+        binding: SpellBinding,
+        color: Color,
+    ) -> SpellSlot {
+        SpellSlot {
+            name: name.to_string(),
+            mana_id,
+            cost,
+            cooldown,
+            binding,
+            color,
+            cooldown_left: 0.0,
+        }
+    }
+
+    fn can_cast(&self, mana: &ManaPool) -> bool {
+        self.cooldown_left <= 0.0 && mana.current >= self.cost
+    }
+}
+
+/// The spells the player can cast, each run by the player rule that drains its mana
+pub(crate) struct Spellbook {
+    pub(crate) slots: Vec<SpellSlot>,
+}
+
+impl Default for Spellbook {
+    fn default() -> Self {
+        Self {
+            slots: vec![
+                SpellSlot::new(
+                    "Create water",
+                    ManaId(0),
+                    10.0,
+                    0.25,
+                    SpellBinding::Key(KeyCode::Key1),
+                    Color::rgb(0.2, 0.4, 1.0),
+                ),
+                SpellSlot::new(
+                    "Ignite",
+                    ManaId(3),
+                    25.0,
+                    1.0,
+                    SpellBinding::Key(KeyCode::Key2),
+                    Color::rgb(1.0, 0.5, 0.1),
+                ),
+                SpellSlot::new(
+                    "Create water at cursor",
+                    ManaId(5),
+                    5.0,
+                    0.1,
+                    SpellBinding::Mouse(MouseButton::Left),
+                    Color::rgb(0.4, 0.7, 1.0),
+                ),
+                SpellSlot::new(
+                    "Fire bolt",
+                    ManaId(6),
+                    30.0,
+                    1.0,
+                    SpellBinding::Key(KeyCode::Key3),
+                    Color::rgb(1.0, 0.2, 0.1),
+                ),
+                SpellSlot::new(
+                    "Dig",
+                    ManaId(4),
+                    15.0,
+                    0.5,
+                    SpellBinding::Mouse(MouseButton::Right),
+                    Color::rgb(0.5, 0.35, 0.2),
+                ),
+            ],
+        }
+    }
+}
+
+impl Spellbook {
+    /// Spend the mana to cast the spell in a slot, if it's ready and there's enough, returning the
+    /// mana to give the caster
+    pub(crate) fn try_cast(&mut self, slot: usize, mana: &mut ManaPool) -> Option<ManaId> {
+        let slot = &mut self.slots[slot];
+        if !slot.can_cast(mana) {
+            return None;
+        }
+        mana.current -= slot.cost;
+        slot.cooldown_left = slot.cooldown;
+        Some(slot.mana_id)
+    }
+
+    /// The slots whose bindings were just pressed
+    pub(crate) fn just_pressed<'a>(
+        &'a self,
+        keys: &'a Input<KeyCode>,
+        buttons: &'a Input<MouseButton>,
+    ) -> impl Iterator<Item = usize> + 'a {
+        (0..self.slots.len()).filter(move |&i| self.slots[i].binding.just_pressed(keys, buttons))
+    }
+
+    fn tick(&mut self, dt: f32) {
+        for slot in self.slots.iter_mut() {
+            slot.cooldown_left = (slot.cooldown_left - dt).max(0.0);
+        }
+    }
+}
+
+/// The mana an entity has to cast spells with
+#[derive(Component)]
+pub(crate) struct ManaPool {
+    pub(crate) current: f32,
+    pub(crate) max: f32,
+    /// Mana regenerated per second
+    pub(crate) regen: f32,
+}
+
+impl Default for ManaPool {
+    fn default() -> Self {
+        Self {
+            current: MAX_MANA,
+            max: MAX_MANA,
+            regen: MANA_REGEN,
+        }
+    }
+}
+
+impl ManaPool {
+    fn regenerate(&mut self, dt: f32) {
+        self.current = (self.current + self.regen * dt).min(self.max);
+    }
+}
+
+/// Keeps the player's spellbook and mana going, and shows them in the HUD
+pub(crate) struct SpellbookPlugin;
+
+impl Plugin for SpellbookPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Spellbook>()
+            .add_startup_system(system_setup_hud)
+            .add_system(system_regenerate_mana)
+            .add_system(system_update_hud);
+    }
+}
+
+/// Regenerate mana and count down cooldowns
+fn system_regenerate_mana(
+    time: Res<Time>,
+    mut spellbook: ResMut<Spellbook>,
+    mut query: Query<&mut ManaPool>,
+) {
+    spellbook.tick(time.delta_seconds());
+    for mut mana in query.iter_mut() {
+        mana.regenerate(time.delta_seconds());
+    }
+}
+
+/// The filled part of the mana bar
+#[derive(Component)]
+struct ManaBar;
+
+/// The row the spellbook's slots are shown in
+#[derive(Component)]
+struct SlotRow;
+
+/// A slot shown in the HUD, by its index in the spellbook
+#[derive(Component)]
+struct SlotIcon(usize);
+
+fn system_setup_hud(mut commands: Commands) {
+    commands.spawn_bundle(UiCameraBundle::default());
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    left: Val::Px(8.0),
+                    bottom: Val::Px(8.0),
+                    ..Default::default()
+                },
+                flex_direction: FlexDirection::ColumnReverse,
+                ..Default::default()
+            },
+            color: Color::NONE.into(),
+            ..Default::default()
+        })
+        // So clicks on the HUD aren't taken as casts
+        .insert(Interaction::default())
+        .with_children(|hud| {
+            hud.spawn_bundle(NodeBundle {
+                style: Style {
+                    size: Size::new(Val::Px(MANA_BAR_SIZE.width), Val::Px(MANA_BAR_SIZE.height)),
+                    margin: Rect {
+                        bottom: Val::Px(4.0),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+                color: Color::rgba(0.0, 0.0, 0.0, 0.5).into(),
+                ..Default::default()
+            })
+            .with_children(|bar| {
+                bar.spawn_bundle(NodeBundle {
+                    style: Style {
+                        size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                        ..Default::default()
+                    },
+                    color: Color::rgb(0.3, 0.5, 1.0).into(),
+                    ..Default::default()
+                })
+                .insert(ManaBar);
+            });
+            hud.spawn_bundle(NodeBundle {
+                color: Color::NONE.into(),
+                ..Default::default()
+            })
+            .insert(SlotRow);
+        });
+}
+
+/// Show the player's mana, and dim the slots that can't be cast right now
+#[allow(clippy::type_complexity)]
+fn system_update_hud(
+    mut commands: Commands,
+    spellbook: Res<Spellbook>,
+    mana_query: Query<&ManaPool, With<Player>>,
+    mut bar_query: Query<&mut Style, With<ManaBar>>,
+    row_query: Query<Entity, With<SlotRow>>,
+    mut icon_query: Query<(Entity, &SlotIcon, &mut UiColor)>,
+    mut shown: Local<Vec<Color>>,
+) {
+    let mana = match mana_query.get_single() {
+        Ok(mana) => mana,
+        Err(_) => return,
+    };
+    for mut style in bar_query.iter_mut() {
+        style.size.width = Val::Percent(100.0 * mana.current / mana.max);
+    }
+
+    // Show the slots again whenever the spellbook changes what they look like. Cooldowns change
+    // it every frame, so compare the slots before rebuilding them.
+    let slots: Vec<Color> = spellbook.slots.iter().map(|slot| slot.color).collect();
+    if spellbook.is_changed() && *shown != slots {
+        for (icon, ..) in icon_query.iter() {
+            commands.entity(icon).despawn_recursive();
+        }
+        let row = match row_query.get_single() {
+            Ok(row) => row,
+            Err(_) => return,
+        };
+        commands.entity(row).with_children(|row| {
+            for (i, slot) in spellbook.slots.iter().enumerate() {
+                row.spawn_bundle(NodeBundle {
+                    style: Style {
+                        size: Size::new(Val::Px(SLOT_SIZE), Val::Px(SLOT_SIZE)),
+                        margin: Rect {
+                            right: Val::Px(4.0),
+                            ..Default::default()
+                        },
+                        ..Default::default()
+                    },
+                    color: slot.color.into(),
+                    ..Default::default()
+                })
+                .insert(SlotIcon(i));
+            }
+        });
+        *shown = slots;
+        return;
+    }
+
+    for (_, &SlotIcon(i), mut color) in icon_query.iter_mut() {
+        let slot = &spellbook.slots[i];
+        let alpha = if slot.can_cast(mana) { 1.0 } else { 0.3 };
+        color.0 = *slot.color.clone().set_a(alpha);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spells::PLAYER_RULES;
+
+    #[test]
+    fn casting_costs_mana_and_waits_for_the_cooldown() {
+        let mut spellbook = Spellbook::default();
+        let mut mana = ManaPool::default();
+        let cost = spellbook.slots[1].cost;
+
+        assert_eq!(spellbook.try_cast(1, &mut mana), Some(ManaId(3)));
+        assert_eq!(mana.current, MAX_MANA - cost);
+        assert_eq!(spellbook.try_cast(1, &mut mana), None);
+        assert_eq!(mana.current, MAX_MANA - cost);
+
+        spellbook.tick(spellbook.slots[1].cooldown);
+        mana.current = cost - 1.0;
+        assert_eq!(spellbook.try_cast(1, &mut mana), None);
+        mana.regenerate(1.0);
+        assert_eq!(spellbook.try_cast(1, &mut mana), Some(ManaId(3)));
+
+        mana.regenerate(1000.0);
+        assert_eq!(mana.current, MAX_MANA);
+    }
+
+    #[test]
+    fn every_slot_has_a_player_rule() {
+        for slot in Spellbook::default().slots {
+            let rule = PLAYER_RULES.iter().find(|r| r.drain == Some(slot.mana_id));
+            let rule = rule.unwrap_or_else(|| panic!("nothing casts {}", slot.name));
+            assert!(
+                rule.spell.estimate().cost <= slot.cost,
+                "{} doesn't pay for its spell",
+                slot.name
+            );
+        }
+    }
+}
@@ -1,62 +1,266 @@
 use crate::blocks::*;
-use crate::cells::GRID_SIZE;
+use crate::cells::{blocks_on_line, GRID_SIZE};
 use crate::chemistry::DynamicProperty::*;
 use crate::chemistry::Property::*;
 use crate::chemistry::StaticProperty::*;
 use crate::chemistry::*;
+use crate::rules::Phase;
+use bevy::math::Vec2;
+use bevy::utils::HashSet;
 use lazy_static::lazy_static;
+use std::borrow::Cow;
 use Spell::*;
 use SpellEffect::*;
 use SpellSelector::*;
 use Target::*;
 
 impl Target {
-    fn for_each_adjacent<F: FnMut(Target)>(&self, info: &WorldInfo, mut f: F) {
+    pub(crate) fn for_each_adjacent<F: FnMut(Target)>(&self, info: &WorldInfo, mut f: F) {
         match self {
             Block(x, y) => {
                 for x2 in -1..2 {
                     for y2 in -1..2 {
-                        if x2 != 0 || y2 != 0 {
-                            if x + x2 >= 0
-                                && x + x2 < GRID_SIZE as i32
-                                && y + y2 >= 0
-                                && y + y2 < GRID_SIZE as i32
-                            {
-                                f(Block(x + x2, y + y2));
-                            }
+                        if (x2 != 0 || y2 != 0)
+                            && x + x2 >= 0
+                            && x + x2 < GRID_SIZE as i32
+                            && y + y2 >= 0
+                            && y + y2 < GRID_SIZE as i32
+                        {
+                            f(Block(x + x2, y + y2));
                         }
                     }
                 }
-                for (&entity, collider) in info.entity_colliders.iter() {
-                    if collider.intersects(&AABBCollider::from_block(*x, *y)) {
-                        f(Entity(entity));
+                let block = AABBCollider::from_block(*x, *y);
+                for (&entity, collider) in info.entity_colliders.intersecting(block) {
+                    match info.entity_parts.get(&entity) {
+                        Some(parts) => {
+                            for part in 0..parts.count() {
+                                if parts.collider(collider, part).intersects(&block) {
+                                    f(Part(entity, part));
+                                }
+                            }
+                        }
+                        None => f(Entity(entity)),
                     }
                 }
             }
             Entity(entity) => {
-                let collider = info.entity_colliders.get(&entity).unwrap();
-                for x in (collider.ll.x.floor() as i32)..=(collider.ur.x.ceil() as i32) {
-                    for y in (collider.ll.y.floor() as i32)..=(collider.ur.y.ceil() as i32) {
-                        if x >= 0 && x < GRID_SIZE as i32 && y >= 0 && y < GRID_SIZE as i32 {
-                            f(Block(x, y));
-                        }
+                let collider = info.entity_colliders.get(entity).unwrap();
+                // Entities split into parts reach the blocks around them, but blocks only reach
+                // them through their parts
+                for_each_block_touching(collider, &mut f);
+                info.parts(*entity).for_each(&mut f);
+                for (&entity2, _) in info.entity_colliders.intersecting(*collider) {
+                    if *entity != entity2 {
+                        f(Entity(entity2));
                     }
                 }
-                for (&entity2, collider2) in info.entity_colliders.iter() {
-                    if *entity != entity2 {
-                        if collider.intersects(collider2) {
-                            f(Entity(entity2));
-                        }
+            }
+            Part(entity, part) => {
+                let parts = info.entity_parts.get(entity).unwrap();
+                for_each_block_touching(&self.bounds(info), &mut f);
+                let (column, row) = parts.cell(*part);
+                let (column, row) = (column as i32, row as i32);
+                for (column2, row2) in [
+                    (column - 1, row),
+                    (column + 1, row),
+                    (column, row - 1),
+                    (column, row + 1),
+                ] {
+                    if let Some(part2) = parts.part(column2, row2) {
+                        f(Part(*entity, part2));
                     }
                 }
+                f(Entity(*entity));
+            }
+        }
+    }
+
+    /// The region of space this target occupies - blocks are treated as a point at their center
+    fn bounds(&self, info: &WorldInfo) -> AABBCollider {
+        match self {
+            Block(x, y) => AABBCollider::from_point(Vec2::new(*x as f32 + 0.5, *y as f32 + 0.5)),
+            Entity(_) | Part(_, _) => info.collider(*self).unwrap(),
+        }
+    }
+
+    /// How strongly spells reach into this target from its neighbors
+    fn conductivity(&self, info: &WorldInfo) -> f32 {
+        match self {
+            Block(x, y) => info.get_block(*x, *y).unwrap().data().conductivity,
+            Entity(_) | Part(_, _) => 1.0,
+        }
+    }
+
+    fn center(&self, info: &WorldInfo) -> Vec2 {
+        let bounds = self.bounds(info);
+        (bounds.ll + bounds.ur) / 2.0
+    }
+
+    /// Call f on every other target within the given radius, along with its distance
+    fn for_each_in_area<F: FnMut(Target, f32)>(&self, info: &WorldInfo, radius: f32, mut f: F) {
+        let bounds = self.bounds(info);
+        let x_min = ((bounds.ll.x - radius).floor() as i32).max(0);
+        let x_max = ((bounds.ur.x + radius).ceil() as i32).min(GRID_SIZE as i32 - 1);
+        let y_min = ((bounds.ll.y - radius).floor() as i32).max(0);
+        let y_max = ((bounds.ur.y + radius).ceil() as i32).min(GRID_SIZE as i32 - 1);
+        for x in x_min..=x_max {
+            for y in y_min..=y_max {
+                let target = Block(x, y);
+                let distance = bounds.distance(&target.bounds(info));
+                if target != *self && distance <= radius {
+                    f(target, distance);
+                }
             }
         }
+        for (&entity, collider) in info.entity_colliders.within(bounds, radius) {
+            for_each_entity_target(info, entity, collider, |target, collider| {
+                let distance = bounds.distance(&collider);
+                if target != *self && distance <= radius {
+                    f(target, distance);
+                }
+            });
+        }
+    }
+
+    /// Call f on every target along a ray, along with its distance, stopping at the first solid
+    /// block
+    fn for_each_on_ray<F: FnMut(Target, f32)>(
+        &self,
+        info: &WorldInfo,
+        direction: Vec2,
+        length: f32,
+        mut f: F,
+    ) {
+        let start = self.center(info);
+        let direction = direction.normalize_or_zero();
+        if direction == Vec2::ZERO {
+            return;
+        }
+
+        let mut ray_length = length;
+        for (x, y, t) in blocks_on_line(start, start + direction * length) {
+            match info.get_block(x, y) {
+                Some(block) => {
+                    f(Block(x, y), t);
+                    if block.data().physics == BlockPhysics::Solid {
+                        ray_length = t;
+                        break;
+                    }
+                }
+                None => {
+                    ray_length = t;
+                    break;
+                }
+            }
+        }
+        let end = start + direction * ray_length;
+        let region = AABBCollider {
+            ll: start.min(end),
+            ur: start.max(end),
+        };
+        for (&entity, collider) in info.entity_colliders.intersecting(region) {
+            for_each_entity_target(info, entity, collider, |target, collider| {
+                if target != *self {
+                    if let Some(t) = collider.ray_hit(start, direction, ray_length) {
+                        f(target, t);
+                    }
+                }
+            });
+        }
+    }
+
+    /// Call f on every target within the given radius that can be seen from this target through
+    /// non-solid blocks, along with its distance
+    fn for_each_visible<F: FnMut(Target, f32)>(&self, info: &WorldInfo, radius: f32, mut f: F) {
+        let start = self.center(info);
+        let is_solid = |x: i32, y: i32| match info.get_block(x, y) {
+            Some(block) => block.data().physics == BlockPhysics::Solid,
+            None => true,
+        };
+
+        // Cast rays towards every block on the edge of the square containing the circle, which
+        // together cover every block inside it
+        let mut seen = HashSet::default();
+        let (x0, y0) = (start.x.floor() as i32, start.y.floor() as i32);
+        let r = radius.ceil() as i32;
+        let edge = (-r..=r).flat_map(|i| [(i, -r), (i, r), (-r, i), (r, i)]);
+        for (dx, dy) in edge {
+            let end = Vec2::new((x0 + dx) as f32 + 0.5, (y0 + dy) as f32 + 0.5);
+            let end = start + (end - start).normalize_or_zero() * radius;
+            for (x, y, t) in blocks_on_line(start, end) {
+                if info.get_block(x, y).is_none() {
+                    break;
+                }
+                if seen.insert((x, y)) {
+                    f(Block(x, y), t);
+                }
+                if is_solid(x, y) {
+                    break;
+                }
+            }
+        }
+
+        let region = AABBCollider::from_point(start);
+        for (&entity, collider) in info.entity_colliders.within(region, radius) {
+            for_each_entity_target(info, entity, collider, |target, collider| {
+                if target == *self {
+                    return;
+                }
+                let direction = (collider.ll + collider.ur) / 2.0 - start;
+                let direction = direction.normalize_or_zero();
+                if let Some(hit) = collider.ray_hit(start, direction, radius) {
+                    let blocked = blocks_on_line(start, start + direction * hit)
+                        .any(|(x, y, t)| t < hit && is_solid(x, y));
+                    if !blocked {
+                        f(target, hit);
+                    }
+                }
+            });
+        }
     }
 }
 
+/// Call f on exactly the blocks whose colliders intersect the given one, so that adjacency between
+/// blocks and entities is symmetric
+fn for_each_block_touching<F: FnMut(Target)>(collider: &AABBCollider, f: &mut F) {
+    for x in ((collider.ll.x - 1.0).ceil() as i32)..=(collider.ur.x.floor() as i32) {
+        for y in ((collider.ll.y - 1.0).ceil() as i32)..=(collider.ur.y.floor() as i32) {
+            if x >= 0 && x < GRID_SIZE as i32 && y >= 0 && y < GRID_SIZE as i32 {
+                f(Block(x, y));
+            }
+        }
+    }
+}
+
+/// Call f on an entity along with its collider, or on each of its parts if it's split into them,
+/// so that spells reaching across an area meet split entities through their parts like blocks do
+fn for_each_entity_target<F: FnMut(Target, AABBCollider)>(
+    info: &WorldInfo,
+    entity: bevy::prelude::Entity,
+    collider: &AABBCollider,
+    mut f: F,
+) {
+    match info.entity_parts.get(&entity) {
+        Some(parts) => {
+            for part in 0..parts.count() {
+                f(Part(entity, part), parts.collider(collider, part));
+            }
+        }
+        None => f(Entity(entity), *collider),
+    }
+}
+
+/// How strongly a target at the given distance is connected to one at the center of a range
+fn falloff(distance: f32, range: f32) -> f32 {
+    1.0 - distance / (range + 1.0)
+}
+
 #[derive(Copy, Clone, Debug)]
 pub(crate) struct SpellTarget {
     pub(crate) target: Target,
+    /// How strongly the target is connected to the spell's source - scales the chance that the
+    /// spell's effects are applied to it
     pub(crate) connection: f32,
 }
 
@@ -69,21 +273,120 @@ impl SpellTarget {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub(crate) enum SpellSelector {
     Adjacent,
+    /// All targets within a radius
+    Area(f32),
+    /// All targets along a ray with a direction and length, up to the first solid block
+    Ray(Vec2, f32),
+    /// The block the target is aiming at
+    AimedAt,
+    /// All targets along a ray of the given length towards the point the target is aiming at, up
+    /// to the first solid block
+    Aimed(f32),
+    /// All targets within a radius that aren't hidden behind solid blocks
+    Sight(f32),
+    /// The target itself, connected as strongly as spells are conducted into it
+    Conducting,
+    /// The target itself, if it has any of the property
     Is(Property),
+    /// The target itself, if its value of the property is at least the threshold
+    Threshold(Property, f32),
     Not(Box<SpellSelector>),
+    /// The target itself, if the quantifier holds for how many of the targets chosen by the first
+    /// selector are also chosen by the second
+    Quantify(Quantifier, Box<SpellSelector>, Box<SpellSelector>),
     Bind(Box<SpellSelector>, Box<SpellSelector>),
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Quantifier {
+    /// At least one target matches
+    Any,
+    /// Every target matches
+    All,
+    /// At least this many targets match
+    AtLeast(u32),
+    /// At least this fraction of the targets match
+    Fraction(f32),
+}
+
+impl Quantifier {
+    fn holds(&self, matches: u32, total: u32) -> bool {
+        match self {
+            Quantifier::Any => matches > 0,
+            Quantifier::All => matches == total,
+            Quantifier::AtLeast(n) => matches >= *n,
+            Quantifier::Fraction(fraction) => matches as f32 >= fraction * total as f32,
+        }
+    }
+}
+
 impl SpellSelector {
     fn select(&self, info: &WorldInfo, target: Target, f: &mut dyn FnMut(SpellTarget)) {
         match self {
             Adjacent => target.for_each_adjacent(info, |a| f(SpellTarget::new(a))),
+            Area(radius) => target.for_each_in_area(info, *radius, |a, distance| {
+                f(SpellTarget {
+                    target: a,
+                    connection: falloff(distance, *radius),
+                })
+            }),
+            Ray(direction, length) => {
+                target.for_each_on_ray(info, *direction, *length, |a, distance| {
+                    f(SpellTarget {
+                        target: a,
+                        connection: falloff(distance, *length),
+                    })
+                })
+            }
+            AimedAt => {
+                if let Some(aim) = info.aim(target) {
+                    let (x, y) = (aim.x.floor() as i32, aim.y.floor() as i32);
+                    if info.get_block(x, y).is_some() {
+                        f(SpellTarget::new(Block(x, y)))
+                    }
+                }
+            }
+            Aimed(length) => {
+                if let Some(aim) = info.aim(target) {
+                    let direction = aim - target.center(info);
+                    target.for_each_on_ray(info, direction, *length, |a, distance| {
+                        f(SpellTarget {
+                            target: a,
+                            connection: falloff(distance, *length),
+                        })
+                    })
+                }
+            }
+            Sight(radius) => target.for_each_visible(info, *radius, |a, distance| {
+                f(SpellTarget {
+                    target: a,
+                    connection: falloff(distance, *radius),
+                })
+            }),
+            Conducting => f(SpellTarget {
+                target,
+                connection: target.conductivity(info),
+            }),
+            // A connection that isn't positive could never apply any effects
             Is(property) => {
-                if info.get(target, *property) != 0.0 {
-                    f(SpellTarget::new(target))
+                let value = info.get(target, *property);
+                if value > 0.0 {
+                    f(SpellTarget {
+                        target,
+                        connection: value,
+                    })
+                }
+            }
+            Threshold(property, threshold) => {
+                let value = info.get(target, *property);
+                if value >= *threshold {
+                    f(SpellTarget {
+                        target,
+                        connection: value,
+                    })
                 }
             }
             Not(selector) => {
@@ -93,15 +396,41 @@ impl SpellSelector {
                     f(SpellTarget::new(target))
                 }
             }
+            Quantify(quantifier, domain, condition) => {
+                let mut total = 0;
+                let mut matches = 0;
+                domain.select(info, target, &mut |candidate| {
+                    let mut is_match = false;
+                    condition.select(info, candidate.target, &mut |_| is_match = true);
+                    total += 1;
+                    if is_match {
+                        matches += 1;
+                    }
+                });
+                if quantifier.holds(matches, total) {
+                    f(SpellTarget::new(target))
+                }
+            }
             Bind(left, right) => SpellSelector::bind(info, target, left, right, f),
         }
     }
 
+    /// List the selectors that are bound together in this one, in the order they're applied
+    fn flatten<'a>(&'a self, selectors: &mut Vec<&'a SpellSelector>) {
+        match self {
+            Bind(left, right) => {
+                left.flatten(selectors);
+                right.flatten(selectors);
+            }
+            selector => selectors.push(selector),
+        }
+    }
+
     fn bind(
         info: &WorldInfo,
         target: Target,
-        left: &Box<SpellSelector>,
-        right: &Box<SpellSelector>,
+        left: &SpellSelector,
+        right: &SpellSelector,
         f: &mut dyn FnMut(SpellTarget),
     ) {
         left.select_spell(info, SpellTarget::new(target), &mut |new_target| {
@@ -124,7 +453,7 @@ impl SpellSelector {
     }
 }
 
-fn bind<I>(selectors: I) -> SpellSelector
+pub(crate) fn bind<I>(selectors: I) -> SpellSelector
 where
     I: IntoIterator<Item = SpellSelector>,
 {
@@ -137,28 +466,43 @@ where
         .unwrap()
 }
 
-fn not(spell: SpellSelector) -> SpellSelector {
+pub(crate) fn not(spell: SpellSelector) -> SpellSelector {
     Not(Box::new(spell))
 }
 
-#[derive(Debug)]
+/// The block right below the target
+fn below() -> SpellSelector {
+    Ray(Vec2::new(0.0, -1.0), 1.0)
+}
+
+/// The target itself, if there's water within a few blocks of it
+fn near_water() -> SpellSelector {
+    Quantify(
+        Quantifier::Any,
+        Box::new(Area(4.0)),
+        Box::new(Is(Material(*WATER))),
+    )
+}
+
+#[derive(Debug, PartialEq)]
 pub(crate) enum SpellEffect {
     Summon,
+    /// Static properties come from the material, so adding, sending or receiving one does nothing
     Add(Property),
+    /// Removing a static property removes the material that provides it
     Remove(Property),
     Send(Property),
     Receive(Property),
 }
 
-// impl SpellEffect {
-//     fn is_valid(&self, info: &WorldInfo, source: Target, target: Target) -> bool {
-//         match self {
-//             Summon => todo!(),
-//             Send(property) => target.get(info, *property) < 1.0,
-//             Receive(property) => source.get(info, *property) < 1.0,
-//         }
-//     }
-// }
+/// A property that every target a spell can affect anything from must have nearby
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Requirement {
+    /// The target itself has the property
+    Has(Property),
+    /// A target adjacent to it has the property
+    AdjacentTo(Property),
+}
 
 #[derive(Debug)]
 pub(crate) struct SpellResult<'a> {
@@ -166,7 +510,7 @@ pub(crate) struct SpellResult<'a> {
     pub(crate) effects: &'a Vec<SpellEffect>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub(crate) enum Spell {
     Effects(Vec<SpellEffect>),
     Select(SpellSelector, Box<Spell>),
@@ -185,18 +529,51 @@ impl Spell {
         }
     }
 
+    /// Find properties that a target must have for this spell to do anything when cast from it
+    pub(crate) fn requirements(&self) -> Vec<Requirement> {
+        let mut selectors = vec![];
+        let mut spell = self;
+        while let Select(selector, inner) = spell {
+            selector.flatten(&mut selectors);
+            spell = inner;
+        }
+
+        let mut requirements = vec![];
+        let mut adjacent = false;
+        for selector in selectors {
+            let property = match selector {
+                Is(property) => Some(*property),
+                Threshold(property, threshold) if *threshold > 0.0 => Some(*property),
+                Threshold(_, _) | Conducting | Not(_) | Quantify(_, _, _) => None,
+                Adjacent if !adjacent => {
+                    adjacent = true;
+                    None
+                }
+                _ => break,
+            };
+            if let Some(property) = property {
+                requirements.push(if adjacent {
+                    Requirement::AdjacentTo(property)
+                } else {
+                    Requirement::Has(property)
+                });
+            }
+        }
+        requirements
+    }
+
     fn cast_select<'a>(
         info: &WorldInfo,
         target: SpellTarget,
         selector: &SpellSelector,
-        spell: &'a Box<Spell>,
+        spell: &'a Spell,
         f: &mut impl FnMut(SpellResult<'a>),
     ) {
         selector.select_spell(info, target, &mut |target| spell.cast(info, target, f))
     }
 }
 
-fn basic<I1, I2>(selectors: I1, effects: I2) -> Spell
+pub(crate) fn basic<I1, I2>(selectors: I1, effects: I2) -> Spell
 where
     I1: IntoIterator<Item = SpellSelector>,
     I2: IntoIterator<Item = SpellEffect>,
@@ -213,31 +590,36 @@ where
 
 #[derive(Debug)]
 pub(crate) struct SpellRule {
-    pub(crate) name: &'static str,
+    pub(crate) name: Cow<'static, str>,
+    /// How many times per second the effects are applied to each target, on average. Rules with an
+    /// infinite rate apply them every step, to each target with a chance of its connection.
     pub(crate) rate: f32,
     pub(crate) drain: Option<ManaId>,
+    pub(crate) phase: Phase,
     pub(crate) spell: Spell,
 }
 
 lazy_static! {
     pub(crate) static ref NATURAL_RULES: Vec<SpellRule> = vec![
         SpellRule {
-            name: "Fire disappears over time",
-            rate: 0.03,
+            name: "Fire disappears over time".into(),
+            rate: 1.8,
             drain: None,
+            phase: Phase::Decay,
             spell: basic(
                 [
                     Is(Dynamic(Burning)),
                     not(Is(Material(*COAL))),
                     // Is(Material(*AIR))
                 ],
-                [Receive(Dynamic(Burning))]
+                [Remove(Dynamic(Burning))]
             )
         },
         SpellRule {
-            name: "Fire makes coal start burning",
-            rate: 0.2,
+            name: "Fire makes coal start burning".into(),
+            rate: 12.0,
             drain: None,
+            phase: Phase::Reactions,
             spell: basic(
                 [
                     Is(Dynamic(Burning)),
@@ -250,21 +632,23 @@ lazy_static! {
             ),
         },
         SpellRule {
-            name: "Coal burns out over time",
-            rate: 0.01,
+            name: "Coal burns out over time".into(),
+            rate: 0.6,
             drain: None,
+            phase: Phase::Decay,
             spell: basic(
                 [
                     Is(Material(*COAL)),
                     Is(Dynamic(Burning))
                 ],
-                [Receive(Dynamic(Burning))]
+                [Remove(Dynamic(Burning))]
             )
         },
         SpellRule {
-            name: "Burning coal lights the air around it on fire",
-            rate: 0.2,
+            name: "Burning coal lights the air around it on fire".into(),
+            rate: 12.0,
             drain: None,
+            phase: Phase::Reactions,
             spell: basic(
                 [
                     Is(Material(*COAL)),
@@ -276,9 +660,10 @@ lazy_static! {
             )
         },
         SpellRule {
-            name: "Burning coal transforms into smoke",
-            rate: 0.005,
+            name: "Burning coal transforms into smoke".into(),
+            rate: 0.3,
             drain: None,
+            phase: Phase::Decay,
             spell: basic(
                 [
                     Is(Material(*COAL)),
@@ -288,15 +673,17 @@ lazy_static! {
             )
         },
         SpellRule {
-            name: "Smoke disappears over time",
-            rate: 0.001,
+            name: "Smoke disappears over time".into(),
+            rate: 0.06,
             drain: None,
+            phase: Phase::Decay,
             spell: basic([Is(Material(*SMOKE))], [Send(Material(*AIR))])
         },
         SpellRule {
-            name: "Fire turns water into steam",
-            rate: 0.02,
+            name: "Fire turns water into steam".into(),
+            rate: 1.2,
             drain: None,
+            phase: Phase::Reactions,
             spell: basic(
                 [
                     Is(Dynamic(Burning)),
@@ -308,15 +695,17 @@ lazy_static! {
             ),
         },
         SpellRule {
-            name: "Steam transforms into water over time",
-            rate: 0.001,
+            name: "Steam transforms into water over time".into(),
+            rate: 0.06,
             drain: None,
+            phase: Phase::Decay,
             spell: basic([Is(Material(*STEAM))], [Send(Material(*WATER))])
         },
         SpellRule {
-            name: "Burning materials light adjacent entities on fire",
-            rate: 0.1,
+            name: "Burning materials light adjacent entities on fire".into(),
+            rate: 6.0,
             drain: None,
+            phase: Phase::Reactions,
             spell: basic(
                 [
                     Is(Dynamic(Burning)),
@@ -327,9 +716,10 @@ lazy_static! {
             ),
         },
         SpellRule {
-            name: "Burning entities light adjacent coal on fire",
-            rate: 0.1,
+            name: "Burning entities light adjacent coal on fire".into(),
+            rate: 6.0,
             drain: None,
+            phase: Phase::Reactions,
             spell: basic(
                 [
                     Is(Dynamic(Burning)),
@@ -340,13 +730,199 @@ lazy_static! {
                 [Send(Dynamic(Burning))]
             ),
         },
+        SpellRule {
+            name: "Burning things give off light".into(),
+            rate: 60.0,
+            drain: None,
+            phase: Phase::Reactions,
+            spell: basic(
+                [
+                    Is(Dynamic(Burning)),
+                    not(Is(Dynamic(Bright))),
+                ],
+                [Add(Dynamic(Bright))]
+            ),
+        },
+        SpellRule {
+            name: "Things stop giving off light once they stop burning".into(),
+            rate: 60.0,
+            drain: None,
+            phase: Phase::Reactions,
+            spell: basic(
+                [
+                    Is(Dynamic(Bright)),
+                    not(Is(Dynamic(Burning))),
+                ],
+                [Remove(Dynamic(Bright))]
+            ),
+        },
+        SpellRule {
+            name: "Electricity sets adjacent flammable blocks on fire".into(),
+            rate: 6.0,
+            drain: None,
+            phase: Phase::Reactions,
+            spell: basic(
+                [
+                    Is(Static(Electric)),
+                    Adjacent,
+                    Is(Static(Flammable)),
+                    not(Is(Dynamic(Burning))),
+                ],
+                [Send(Dynamic(Burning))]
+            ),
+        },
+        SpellRule {
+            name: "Fire spreads to flammable blocks".into(),
+            rate: 6.0,
+            drain: None,
+            phase: Phase::Reactions,
+            spell: basic(
+                [
+                    Is(Dynamic(Burning)),
+                    Adjacent,
+                    Conducting,
+                    Is(Static(Flammable)),
+                    not(Is(Dynamic(Burning))),
+                ],
+                [Send(Dynamic(Burning))]
+            ),
+        },
+        SpellRule {
+            name: "Burning grass leaves dirt behind".into(),
+            rate: 1.0,
+            drain: None,
+            phase: Phase::Reactions,
+            spell: basic(
+                [Is(Material(*GRASS)), Is(Dynamic(Burning))],
+                [Send(Material(*DIRT))]
+            ),
+        },
+        SpellRule {
+            name: "Burning vines burn away".into(),
+            rate: 1.0,
+            drain: None,
+            phase: Phase::Reactions,
+            spell: basic(
+                [Is(Material(*VINE)), Is(Dynamic(Burning))],
+                [Send(Material(*AIR))]
+            ),
+        },
+        SpellRule {
+            name: "Burning seeds burn away".into(),
+            rate: 1.0,
+            drain: None,
+            phase: Phase::Reactions,
+            spell: basic(
+                [Is(Material(*SEED)), Is(Dynamic(Burning))],
+                [Send(Material(*AIR))]
+            ),
+        },
+        SpellRule {
+            name: "Grass grows on lit dirt near water".into(),
+            rate: 0.05,
+            drain: None,
+            phase: Phase::Growth,
+            spell: basic(
+                [
+                    Is(Material(*DIRT)),
+                    Threshold(Static(Light), 0.5),
+                    near_water(),
+                ],
+                [Send(Material(*GRASS))]
+            ),
+        },
+        SpellRule {
+            name: "Grass spreads to lit dirt".into(),
+            rate: 0.1,
+            drain: None,
+            phase: Phase::Growth,
+            spell: basic(
+                [
+                    Is(Material(*GRASS)),
+                    Adjacent,
+                    Is(Material(*DIRT)),
+                    Threshold(Static(Light), 0.5),
+                ],
+                [Send(Material(*GRASS))]
+            ),
+        },
+        SpellRule {
+            name: "Grass turns back into dirt in the dark".into(),
+            rate: 0.1,
+            drain: None,
+            phase: Phase::Growth,
+            spell: basic(
+                [
+                    Is(Material(*GRASS)),
+                    not(Threshold(Static(Light), 0.2)),
+                ],
+                [Send(Material(*DIRT))]
+            ),
+        },
+        SpellRule {
+            name: "Seeds sprout on dirt near water".into(),
+            rate: 0.2,
+            drain: None,
+            phase: Phase::Growth,
+            spell: basic(
+                [
+                    Is(Material(*SEED)),
+                    Quantify(Quantifier::Any, Box::new(below()), Box::new(Is(Material(*DIRT)))),
+                    near_water(),
+                ],
+                [Send(Material(*GRASS))]
+            ),
+        },
+        SpellRule {
+            name: "Vines hang down from grass".into(),
+            rate: 0.02,
+            drain: None,
+            phase: Phase::Growth,
+            spell: basic(
+                [Is(Material(*GRASS)), below(), Is(Material(*AIR))],
+                [Send(Material(*VINE))]
+            ),
+        },
+        SpellRule {
+            name: "Vines grow down into the light".into(),
+            rate: 0.1,
+            drain: None,
+            phase: Phase::Growth,
+            spell: basic(
+                [
+                    Is(Material(*VINE)),
+                    below(),
+                    Is(Material(*AIR)),
+                    Threshold(Static(Light), 0.3),
+                ],
+                [Send(Material(*VINE))]
+            ),
+        },
+        SpellRule {
+            name: "Vines wither away from water".into(),
+            rate: 0.05,
+            drain: None,
+            phase: Phase::Growth,
+            spell: basic(
+                [Is(Material(*VINE)), not(near_water())],
+                [Send(Material(*AIR))]
+            ),
+        },
+        SpellRule {
+            name: "Charge wears off over time".into(),
+            rate: 0.3,
+            drain: None,
+            phase: Phase::Decay,
+            spell: basic([Is(Dynamic(Charged))], [Remove(Dynamic(Charged))]),
+        },
     ];
 
     pub(crate) static ref PLAYER_RULES: Vec<SpellRule> = vec![
         SpellRule {
-            name: "Create water",
+            name: "Create water".into(),
             rate: f32::INFINITY,
             drain: Some(ManaId(0)),
+            phase: Phase::Player,
             spell: basic(
                 [
                     Adjacent,
@@ -356,9 +932,10 @@ lazy_static! {
             )
         },
         SpellRule {
-            name: "Launch fireball",
+            name: "Launch fireball".into(),
             rate: f32::INFINITY,
             drain: Some(ManaId(1)),
+            phase: Phase::Player,
             spell: basic(
                 [],
                 [
@@ -369,17 +946,359 @@ lazy_static! {
             )
         },
         SpellRule {
-            name: "Fireball",
+            name: "Fireball".into(),
             rate: f32::INFINITY,
             drain: Some(ManaId(2)),
+            phase: Phase::Player,
             spell: basic(
                 [
                     Adjacent,
                     not(Is(Material(*AIR))),
-                    Adjacent, // TODO: Area(5)
+                    Area(5.0),
                 ],
                 [Add(Material(*FIRE))],
             )
         },
+        SpellRule {
+            name: "Ignite".into(),
+            rate: f32::INFINITY,
+            drain: Some(ManaId(3)),
+            phase: Phase::Player,
+            spell: basic(
+                [
+                    Adjacent,
+                    Is(Static(Flammable)),
+                ],
+                [Send(Dynamic(Burning))],
+            )
+        },
+        SpellRule {
+            name: "Dig".into(),
+            rate: f32::INFINITY,
+            drain: Some(ManaId(4)),
+            phase: Phase::Player,
+            spell: basic(
+                [
+                    Adjacent,
+                    not(Is(Material(*AIR))),
+                ],
+                [Send(Material(*AIR))],
+            )
+        },
+        SpellRule {
+            name: "Create water at cursor".into(),
+            rate: f32::INFINITY,
+            drain: Some(ManaId(5)),
+            phase: Phase::Player,
+            spell: basic(
+                [
+                    AimedAt,
+                    Is(Material(*AIR)),
+                ],
+                [Add(Material(*WATER))],
+            )
+        },
+        SpellRule {
+            name: "Fire bolt".into(),
+            rate: f32::INFINITY,
+            drain: Some(ManaId(6)),
+            phase: Phase::Player,
+            spell: basic(
+                [
+                    Aimed(60.0),
+                    Is(Static(Flammable)),
+                ],
+                [Send(Dynamic(Burning))],
+            )
+        },
     ];
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cells::set_block_range;
+
+    fn selected(selector: SpellSelector, info: &WorldInfo, source: Target) -> Vec<SpellTarget> {
+        let mut results = vec![];
+        selector.select(info, source, &mut |t| results.push(t));
+        results
+    }
+
+    fn selects(results: &[SpellTarget], target: Target) -> bool {
+        results.iter().any(|r| r.target == target)
+    }
+
+    #[test]
+    fn area_selects_within_radius() {
+        let info = WorldInfo::default();
+        let results = selected(Area(2.0), &info, Block(10, 10));
+        assert!(selects(&results, Block(12, 10)));
+        assert!(selects(&results, Block(11, 11)));
+        assert!(!selects(&results, Block(12, 12)));
+        assert!(!selects(&results, Block(10, 10)));
+        assert_eq!(results.len(), 12);
+    }
+
+    #[test]
+    fn area_connection_falls_off_with_distance() {
+        let info = WorldInfo::default();
+        let results = selected(Area(3.0), &info, Block(10, 10));
+        let connection = |target| {
+            results
+                .iter()
+                .find(|r| r.target == target)
+                .unwrap()
+                .connection
+        };
+        assert!(connection(Block(11, 10)) > connection(Block(13, 10)));
+        assert!(connection(Block(13, 10)) > 0.0);
+    }
+
+    #[test]
+    fn area_selects_entities() {
+        let mut info = WorldInfo::default();
+        let entity = bevy::prelude::Entity::from_raw(0);
+        info.entity_colliders.insert(
+            entity,
+            AABBCollider {
+                ll: Vec2::new(14.0, 10.0),
+                ur: Vec2::new(16.0, 12.0),
+            },
+        );
+        assert!(selects(
+            &selected(Area(4.0), &info, Block(10, 10)),
+            Entity(entity)
+        ));
+        assert!(!selects(
+            &selected(Area(3.0), &info, Block(10, 10)),
+            Entity(entity)
+        ));
+    }
+
+    #[test]
+    fn blocks_touch_the_parts_of_split_entities() {
+        let mut info = WorldInfo::default();
+        let entity = bevy::prelude::Entity::from_raw(0);
+        info.entity_colliders.insert(
+            entity,
+            AABBCollider {
+                ll: Vec2::new(10.0, 10.0),
+                ur: Vec2::new(14.0, 14.0),
+            },
+        );
+        info.entity_parts.insert(
+            entity,
+            BodyParts {
+                columns: 2,
+                rows: 2,
+            },
+        );
+
+        // The block left of the bottom left part only touches that part
+        let results = selected(Adjacent, &info, Block(9, 10));
+        assert!(selects(&results, Part(entity, 0)));
+        assert!(!selects(&results, Part(entity, 1)));
+        assert!(!selects(&results, Entity(entity)));
+
+        let results = selected(Adjacent, &info, Part(entity, 0));
+        assert!(selects(&results, Block(9, 10)));
+        assert!(!selects(&results, Block(14, 10)));
+        assert!(selects(&results, Part(entity, 1)));
+        assert!(selects(&results, Part(entity, 2)));
+        assert!(!selects(&results, Part(entity, 3)));
+        assert!(selects(&results, Entity(entity)));
+
+        let results = selected(Adjacent, &info, Entity(entity));
+        assert!(selects(&results, Part(entity, 3)));
+        assert!(selects(&results, Block(9, 10)));
+    }
+
+    #[test]
+    fn entities_can_be_split_into_more_parts_than_fit_in_a_byte() {
+        let mut info = WorldInfo::default();
+        let entity = bevy::prelude::Entity::from_raw(0);
+        info.entity_colliders.insert(
+            entity,
+            AABBCollider {
+                ll: Vec2::new(10.0, 10.0),
+                ur: Vec2::new(26.0, 26.0),
+            },
+        );
+        info.entity_parts.insert(
+            entity,
+            BodyParts {
+                columns: 16,
+                rows: 16,
+            },
+        );
+        assert_eq!(info.parts(entity).count(), 256);
+
+        // The block right of the top right part only touches that part
+        let results = selected(Adjacent, &info, Block(26, 25));
+        assert!(selects(&results, Part(entity, 255)));
+        assert!(!selects(&results, Part(entity, 254)));
+    }
+
+    #[test]
+    fn ranged_selectors_reach_the_parts_of_split_entities() {
+        let mut info = WorldInfo::default();
+        let entity = bevy::prelude::Entity::from_raw(0);
+        info.entity_colliders.insert(
+            entity,
+            AABBCollider {
+                ll: Vec2::new(14.0, 10.0),
+                ur: Vec2::new(18.0, 14.0),
+            },
+        );
+        info.entity_parts.insert(
+            entity,
+            BodyParts {
+                columns: 2,
+                rows: 2,
+            },
+        );
+
+        // Only the near column is in range, like only the near side is adjacent
+        let results = selected(Area(4.0), &info, Block(10, 10));
+        assert!(selects(&results, Part(entity, 0)));
+        assert!(!selects(&results, Part(entity, 1)));
+        assert!(!selects(&results, Entity(entity)));
+
+        let results = selected(Ray(Vec2::X, 10.0), &info, Block(10, 10));
+        assert!(selects(&results, Part(entity, 0)));
+        assert!(selects(&results, Part(entity, 1)));
+        assert!(!selects(&results, Part(entity, 2)));
+        assert!(!selects(&results, Entity(entity)));
+
+        let results = selected(Sight(10.0), &info, Block(10, 10));
+        assert!(selects(&results, Part(entity, 2)));
+        assert!(!selects(&results, Entity(entity)));
+    }
+
+    #[test]
+    fn ray_stops_at_first_solid_block() {
+        let mut info = WorldInfo::default();
+        set_block_range(&mut info, 14..15, 0..20, *STONE);
+        let results = selected(Ray(Vec2::X, 10.0), &info, Block(10, 10));
+        let targets: Vec<_> = results.iter().map(|r| r.target).collect();
+        assert_eq!(
+            targets,
+            vec![Block(11, 10), Block(12, 10), Block(13, 10), Block(14, 10)]
+        );
+    }
+
+    #[test]
+    fn sight_is_blocked_by_solid_blocks() {
+        let mut info = WorldInfo::default();
+        set_block_range(&mut info, 12..13, 5..16, *STONE);
+        set_block_range(&mut info, 8..9, 5..16, *WATER);
+        let results = selected(Sight(5.0), &info, Block(10, 10));
+        assert!(selects(&results, Block(12, 10)));
+        assert!(!selects(&results, Block(13, 10)));
+        assert!(selects(&results, Block(7, 10)));
+        assert!(selects(&results, Block(10, 14)));
+    }
+
+    #[test]
+    fn aimed_spells_reach_towards_the_aim() {
+        let mut info = WorldInfo::default();
+        let entity = bevy::prelude::Entity::from_raw(0);
+        info.entity_colliders.insert(
+            entity,
+            AABBCollider {
+                ll: Vec2::new(10.0, 10.0),
+                ur: Vec2::new(12.0, 12.0),
+            },
+        );
+        set_block_range(&mut info, 30..31, 0..20, *STONE);
+        assert!(selected(AimedAt, &info, Entity(entity)).is_empty());
+
+        info.aims.insert(entity, Vec2::new(40.5, 11.5));
+        let results = selected(AimedAt, &info, Entity(entity));
+        assert_eq!(results.len(), 1);
+        assert!(selects(&results, Block(40, 11)));
+
+        // The ray stops at the stone before it reaches the aim
+        let results = selected(Aimed(50.0), &info, Entity(entity));
+        assert!(selects(&results, Block(20, 11)));
+        assert!(selects(&results, Block(30, 11)));
+        assert!(!selects(&results, Block(31, 11)));
+        assert!(!selects(&results, Block(20, 15)));
+    }
+
+    /// Surround a block with burning air, except for the given neighbors
+    fn surround_with_fire(info: &mut WorldInfo, x: i32, y: i32, except: &[(i32, i32)]) {
+        for (x2, y2) in crate::cells::neighbors(x, y, -1..2, -1..2) {
+            if (x2, y2) != (x, y) && !except.contains(&(x2, y2)) {
+                info.set(Block(x2, y2), Burning, 1.0);
+            }
+        }
+    }
+
+    fn quantify(quantifier: Quantifier) -> SpellSelector {
+        Quantify(
+            quantifier,
+            Box::new(Adjacent),
+            Box::new(Is(Dynamic(Burning))),
+        )
+    }
+
+    #[test]
+    fn all_requires_every_neighbor() {
+        let mut info = WorldInfo::default();
+        surround_with_fire(&mut info, 10, 10, &[]);
+        surround_with_fire(&mut info, 20, 10, &[(21, 11)]);
+        assert!(selects(
+            &selected(quantify(Quantifier::All), &info, Block(10, 10)),
+            Block(10, 10)
+        ));
+        assert!(selected(quantify(Quantifier::All), &info, Block(20, 10)).is_empty());
+        assert!(!selected(quantify(Quantifier::Any), &info, Block(20, 10)).is_empty());
+    }
+
+    #[test]
+    fn at_least_and_fraction_count_matches() {
+        let mut info = WorldInfo::default();
+        surround_with_fire(&mut info, 10, 10, &[(9, 9), (10, 9), (11, 9), (9, 10)]);
+        let source = Block(10, 10);
+        assert!(!selected(quantify(Quantifier::AtLeast(4)), &info, source).is_empty());
+        assert!(selected(quantify(Quantifier::AtLeast(5)), &info, source).is_empty());
+        assert!(!selected(quantify(Quantifier::Fraction(0.5)), &info, source).is_empty());
+        assert!(selected(quantify(Quantifier::Fraction(0.6)), &info, source).is_empty());
+    }
+
+    #[test]
+    fn threshold_compares_values() {
+        let mut info = WorldInfo::default();
+        info.set(Block(10, 10), Burning, 0.3);
+        let source = Block(10, 10);
+        assert!(!selected(Threshold(Dynamic(Burning), 0.3), &info, source).is_empty());
+        assert!(selected(Threshold(Dynamic(Burning), 0.5), &info, source).is_empty());
+    }
+
+    #[test]
+    fn conducting_connection_uses_conductivity() {
+        let mut info = WorldInfo::default();
+        set_block_range(&mut info, 11..12, 10..11, *STONE);
+        let results = selected(Adjacent, &info, Block(10, 10));
+        assert!(results.iter().all(|r| r.connection == 1.0));
+
+        let conducted = Bind(Box::new(Adjacent), Box::new(Conducting));
+        let results = selected(conducted, &info, Block(10, 10));
+        let stone = results.iter().find(|r| r.target == Block(11, 10)).unwrap();
+        let air = results.iter().find(|r| r.target == Block(9, 10)).unwrap();
+        assert_eq!(stone.connection, 0.2);
+        assert_eq!(air.connection, 1.0);
+    }
+
+    #[test]
+    fn is_connection_uses_property_value() {
+        let mut info = WorldInfo::default();
+        info.set(Block(10, 10), Burning, 0.25);
+        let results = selected(Is(Dynamic(Burning)), &info, Block(10, 10));
+        assert_eq!(results[0].connection, 0.25);
+        info.set(Block(10, 10), Burning, -0.25);
+        assert!(selected(Is(Dynamic(Burning)), &info, Block(10, 10)).is_empty());
+    }
+}
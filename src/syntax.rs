@@ -0,0 +1,417 @@
+//! Spells written out in the same style as natural.rules, e.g.
+//! `adjacent (not Air) (area 5) => (add Fire)`, and parsed back again.
+//!
+//! Selectors are written one after another, each choosing targets from the targets chosen by the
+//! one before it. Selectors taking arguments, and selectors bound together inside other selectors,
+//! go in parentheses. The effects come after `=>`.
+
+use crate::blocks::ALL_BLOCK_DATA;
+use crate::chemistry::DynamicProperty::*;
+use crate::chemistry::Property::*;
+use crate::chemistry::StaticProperty::*;
+use crate::chemistry::*;
+use crate::spells::*;
+use bevy::math::Vec2;
+use nom::{
+    branch::alt,
+    bytes::complete::{tag, take_while1},
+    character::complete::{char, multispace0, multispace1, u32 as count, u8 as mana_id},
+    combinator::{all_consuming, map, map_opt, opt, verify},
+    multi::{many0, separated_list1},
+    number::complete::float,
+    sequence::{delimited, pair, preceded, separated_pair, terminated, tuple},
+    IResult,
+};
+use std::fmt;
+use std::str::FromStr;
+use Spell::*;
+use SpellEffect::*;
+use SpellSelector::*;
+
+/// The properties written with their own names - materials are written with the name of their
+/// block, and mana with its id
+const NAMED_PROPERTIES: [Property; 9] = [
+    Dynamic(Burning),
+    Dynamic(Bright),
+    Dynamic(Charged),
+    Dynamic(Forwards),
+    Static(IsEntity),
+    Static(Liquid),
+    Static(Light),
+    Static(Electric),
+    Static(Flammable),
+];
+
+impl fmt::Display for Property {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Material(id) => write!(f, "{}", ALL_BLOCK_DATA[*id as usize].name),
+            Dynamic(Mana(ManaId(id))) => write!(f, "(Mana {})", id),
+            Dynamic(property) => write!(f, "{:?}", property),
+            Static(property) => write!(f, "{:?}", property),
+        }
+    }
+}
+
+impl fmt::Display for Quantifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Quantifier::Any => write!(f, "any"),
+            Quantifier::All => write!(f, "all"),
+            Quantifier::AtLeast(n) => write!(f, "at-least {}", n),
+            Quantifier::Fraction(fraction) => write!(f, "fraction {}", fraction),
+        }
+    }
+}
+
+/// Selectors bound together, written one after another without parentheses around them
+struct Sequence<'a>(&'a SpellSelector);
+
+impl fmt::Display for Sequence<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Bind(first, rest) => write!(f, "{} {}", first, Sequence(rest)),
+            selector => write!(f, "{}", selector),
+        }
+    }
+}
+
+impl fmt::Display for SpellSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Adjacent => write!(f, "adjacent"),
+            Area(radius) => write!(f, "(area {})", radius),
+            Ray(direction, length) => {
+                write!(f, "(ray {} {} {})", direction.x, direction.y, length)
+            }
+            AimedAt => write!(f, "aimed-at"),
+            Aimed(length) => write!(f, "(aimed {})", length),
+            Sight(radius) => write!(f, "(sight {})", radius),
+            Conducting => write!(f, "conducting"),
+            Is(property) => write!(f, "{}", property),
+            Threshold(property, threshold) => write!(f, "({} at-least {})", property, threshold),
+            Not(selector) => write!(f, "(not {})", Sequence(selector)),
+            Quantify(quantifier, over, matching) => {
+                write!(f, "({} {} {})", quantifier, over, matching)
+            }
+            Bind(_, _) => write!(f, "({})", Sequence(self)),
+        }
+    }
+}
+
+impl fmt::Display for SpellEffect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Summon => write!(f, "(summon)"),
+            Add(property) => write!(f, "(add {})", property),
+            Remove(property) => write!(f, "(remove {})", property),
+            Send(property) => write!(f, "(send {})", property),
+            Receive(property) => write!(f, "(receive {})", property),
+        }
+    }
+}
+
+impl fmt::Display for Spell {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Effects(effects) => {
+                write!(f, "=>")?;
+                for effect in effects {
+                    write!(f, " {}", effect)?;
+                }
+                Ok(())
+            }
+            // Spells selecting again after a selection are separated by commas, so they can be
+            // told apart from selectors that are bound together
+            Select(selector, spell) if matches!(**spell, Select(_, _)) => {
+                write!(f, "{}, {}", Sequence(selector), spell)
+            }
+            Select(selector, spell) => write!(f, "{} {}", Sequence(selector), spell),
+        }
+    }
+}
+
+impl fmt::Display for SpellRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.rate, self.spell)
+    }
+}
+
+/// Why a spell couldn't be parsed
+#[derive(Debug)]
+pub(crate) struct SyntaxError(String);
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid spell: {}", self.0)
+    }
+}
+
+impl FromStr for Spell {
+    type Err = SyntaxError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        all_consuming(delimited(multispace0, spell, multispace0))(s)
+            .map(|(_, spell)| spell)
+            .map_err(|e| SyntaxError(e.to_string()))
+    }
+}
+
+type ParseResult<'a, T> = IResult<&'a str, T>;
+
+fn word(input: &str) -> ParseResult<'_, &str> {
+    take_while1(|c: char| c.is_alphanumeric() || c == '-')(input)
+}
+
+/// A whole word that's exactly the keyword
+fn keyword<'a>(name: &'static str) -> impl FnMut(&'a str) -> ParseResult<'a, &'a str> {
+    verify(word, move |word: &str| word == name)
+}
+
+/// A keyword followed by the whitespace before its arguments
+fn keyword_then<'a>(name: &'static str) -> impl FnMut(&'a str) -> ParseResult<'a, &'a str> {
+    terminated(keyword(name), multispace1)
+}
+
+fn parens<'a, T>(
+    inner: impl FnMut(&'a str) -> ParseResult<'a, T>,
+) -> impl FnMut(&'a str) -> ParseResult<'a, T> {
+    delimited(
+        pair(char('('), multispace0),
+        inner,
+        pair(multispace0, char(')')),
+    )
+}
+
+/// Whitespace, then something
+fn spaced<'a, T>(
+    inner: impl FnMut(&'a str) -> ParseResult<'a, T>,
+) -> impl FnMut(&'a str) -> ParseResult<'a, T> {
+    preceded(multispace1, inner)
+}
+
+fn property_named(name: &str) -> Option<Property> {
+    NAMED_PROPERTIES
+        .into_iter()
+        .find(|property| property.to_string() == name)
+        .or_else(|| {
+            let id = ALL_BLOCK_DATA.iter().position(|block| block.name == name)?;
+            Some(Material(id as u16))
+        })
+}
+
+fn property(input: &str) -> ParseResult<'_, Property> {
+    alt((
+        map(parens(preceded(keyword_then("Mana"), mana_id)), |id| {
+            Dynamic(Mana(ManaId(id)))
+        }),
+        map_opt(word, property_named),
+    ))(input)
+}
+
+fn quantifier(input: &str) -> ParseResult<'_, Quantifier> {
+    alt((
+        map(keyword("any"), |_| Quantifier::Any),
+        map(keyword("all"), |_| Quantifier::All),
+        map(
+            preceded(keyword_then("at-least"), count),
+            Quantifier::AtLeast,
+        ),
+        map(
+            preceded(keyword_then("fraction"), float),
+            Quantifier::Fraction,
+        ),
+    ))(input)
+}
+
+/// A single selector, or selectors bound together in parentheses
+fn selector(input: &str) -> ParseResult<'_, SpellSelector> {
+    alt((
+        map(keyword("adjacent"), |_| Adjacent),
+        map(keyword("aimed-at"), |_| AimedAt),
+        map(keyword("conducting"), |_| Conducting),
+        map(property, Is),
+        parens(alt((
+            map(preceded(keyword_then("area"), float), Area),
+            map(preceded(keyword_then("aimed"), float), Aimed),
+            map(preceded(keyword_then("sight"), float), Sight),
+            map(
+                preceded(
+                    keyword_then("ray"),
+                    tuple((float, spaced(float), spaced(float))),
+                ),
+                |(x, y, length)| Ray(Vec2::new(x, y), length),
+            ),
+            map(
+                separated_pair(property, spaced(keyword_then("at-least")), float),
+                |(property, threshold)| Threshold(property, threshold),
+            ),
+            map(preceded(keyword_then("not"), sequence), |selector| {
+                Not(Box::new(selector))
+            }),
+            map(
+                tuple((quantifier, spaced(selector), spaced(selector))),
+                |(quantifier, over, matching)| {
+                    Quantify(quantifier, Box::new(over), Box::new(matching))
+                },
+            ),
+            sequence,
+        ))),
+    ))(input)
+}
+
+/// Selectors one after another, bound together
+fn sequence(input: &str) -> ParseResult<'_, SpellSelector> {
+    map(separated_list1(multispace1, selector), |selectors| {
+        bind(selectors)
+    })(input)
+}
+
+fn effect(input: &str) -> ParseResult<'_, SpellEffect> {
+    parens(alt((
+        map(keyword("summon"), |_| Summon),
+        map(preceded(keyword_then("add"), property), Add),
+        map(preceded(keyword_then("remove"), property), Remove),
+        map(preceded(keyword_then("send"), property), Send),
+        map(preceded(keyword_then("receive"), property), Receive),
+    )))(input)
+}
+
+fn spell(input: &str) -> ParseResult<'_, Spell> {
+    alt((
+        map(preceded(tag("=>"), many0(spaced(effect))), Effects),
+        map(
+            tuple((
+                sequence,
+                multispace0,
+                opt(pair(char(','), multispace0)),
+                spell,
+            )),
+            |(selector, _, _, spell)| Select(selector, Box::new(spell)),
+        ),
+    ))(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blocks::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn round_trip(spell: &Spell) {
+        let written = spell.to_string();
+        let parsed: Spell = written
+            .parse()
+            .unwrap_or_else(|e| panic!("{}: {}", written, e));
+        assert_eq!(&parsed, spell, "{}", written);
+    }
+
+    #[test]
+    fn spells_are_written_like_natural_rules() {
+        let spell = basic(
+            [Adjacent, not(Is(Material(*AIR))), Area(5.0)],
+            [Add(Material(*FIRE))],
+        );
+        assert_eq!(
+            spell.to_string(),
+            "adjacent (not Air) (area 5) => (add Fire)"
+        );
+        let spell = basic(
+            [Quantify(
+                Quantifier::Any,
+                Box::new(Ray(Vec2::new(0.0, -1.0), 1.0)),
+                Box::new(Is(Material(*DIRT))),
+            )],
+            [Remove(Dynamic(Mana(ManaId(2))))],
+        );
+        assert_eq!(
+            spell.to_string(),
+            "(any (ray 0 -1 1) Dirt) => (remove (Mana 2))"
+        );
+    }
+
+    #[test]
+    fn built_in_spells_round_trip() {
+        for rule in NATURAL_RULES.iter().chain(PLAYER_RULES.iter()) {
+            round_trip(&rule.spell);
+        }
+    }
+
+    fn random_property(rng: &mut StdRng) -> Property {
+        match rng.gen_range(0..3) {
+            0 => NAMED_PROPERTIES[rng.gen_range(0..NAMED_PROPERTIES.len())],
+            1 => Dynamic(Mana(ManaId(rng.gen()))),
+            _ => Material(rng.gen_range(0..ALL_BLOCK_DATA.len()) as u16),
+        }
+    }
+
+    fn random_selector(rng: &mut StdRng, depth: u32) -> SpellSelector {
+        let boxed = |rng: &mut StdRng| Box::new(random_selector(rng, depth + 1));
+        // Stop nesting selectors after a few levels
+        let kinds = if depth > 3 { 8 } else { 12 };
+        match rng.gen_range(0..kinds) {
+            0 => Adjacent,
+            1 => Area(rng.gen_range(0.0..10.0)),
+            2 => Ray(Vec2::new(rng.gen(), -rng.gen::<f32>()), rng.gen()),
+            3 => AimedAt,
+            4 => Aimed(rng.gen()),
+            5 => Sight(rng.gen()),
+            6 => Conducting,
+            7 => Is(random_property(rng)),
+            8 => Threshold(random_property(rng), rng.gen()),
+            9 => Not(boxed(rng)),
+            10 => {
+                let quantifier = match rng.gen_range(0..4) {
+                    0 => Quantifier::Any,
+                    1 => Quantifier::All,
+                    2 => Quantifier::AtLeast(rng.gen()),
+                    _ => Quantifier::Fraction(rng.gen()),
+                };
+                Quantify(quantifier, boxed(rng), boxed(rng))
+            }
+            _ => Bind(boxed(rng), boxed(rng)),
+        }
+    }
+
+    fn random_spell(rng: &mut StdRng) -> Spell {
+        if rng.gen_bool(0.3) {
+            let effects = (0..rng.gen_range(0..3))
+                .map(|_| {
+                    let property = random_property(rng);
+                    match rng.gen_range(0..5) {
+                        0 => Summon,
+                        1 => Add(property),
+                        2 => Remove(property),
+                        3 => Send(property),
+                        _ => Receive(property),
+                    }
+                })
+                .collect();
+            Effects(effects)
+        } else {
+            Select(random_selector(rng, 0), Box::new(random_spell(rng)))
+        }
+    }
+
+    #[test]
+    fn random_spells_round_trip() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..1000 {
+            round_trip(&random_spell(&mut rng));
+        }
+    }
+
+    #[test]
+    fn invalid_spells_are_rejected() {
+        for written in [
+            "",
+            "adjacent",
+            "Unobtainium => (add Air)",
+            "=> (add)",
+            "(not) =>",
+        ] {
+            assert!(written.parse::<Spell>().is_err(), "{}", written);
+        }
+    }
+}